@@ -0,0 +1,107 @@
+use std::{fs, io};
+use std::path::Path;
+
+use crate::cpu::joypad::Button;
+
+// Inputs bound to each button, by name. Keys use the winit key code names,
+// such as `KeyW`, `ArrowUp` or `Enter`, and gamepad inputs start with `Pad`:
+// `PadUp`/`PadDown`/`PadLeft`/`PadRight` for the d-pad, `PadStickUp` and so
+// on for the left stick, and `PadSouth`/`PadEast`/`PadNorth`/`PadWest`,
+// `PadStart` and `PadSelect` for the buttons.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Bindings {
+    // Indexed by `Button as usize`
+    inputs: [Vec<String>; 8],
+    turbo: [Vec<String>; 8]
+}
+
+fn button_from_name(name: &str) -> Option<Button> {
+    match name {
+        "right" => Some(Button::R),
+        "left" => Some(Button::L),
+        "up" => Some(Button::U),
+        "down" => Some(Button::D),
+        "a" => Some(Button::A),
+        "b" => Some(Button::B),
+        "select" => Some(Button::SEL),
+        "start" => Some(Button::STA),
+        _ => None
+    }
+}
+
+impl Bindings {
+
+    // WASD, I and J for A and B, N for start and B for select, plus the
+    // d-pad, left stick and face buttons of a gamepad, with A and B where
+    // they are on a Game Boy. O and H, or the other two face buttons, are
+    // turbo A and B.
+    pub fn new() -> Bindings {
+        let inputs = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        Bindings {
+            inputs: [
+                inputs(&["KeyD", "PadRight", "PadStickRight"]),
+                inputs(&["KeyA", "PadLeft", "PadStickLeft"]),
+                inputs(&["KeyW", "PadUp", "PadStickUp"]),
+                inputs(&["KeyS", "PadDown", "PadStickDown"]),
+                inputs(&["KeyI", "PadEast"]),
+                inputs(&["KeyJ", "PadSouth"]),
+                inputs(&["KeyB", "PadSelect"]),
+                inputs(&["KeyN", "PadStart"])
+            ],
+            turbo: [
+                vec![],
+                vec![],
+                vec![],
+                vec![],
+                inputs(&["KeyO", "PadNorth"]),
+                inputs(&["KeyH", "PadWest"]),
+                vec![],
+                vec![]
+            ]
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Bindings> {
+        Bindings::parse(&fs::read_to_string(path)?)
+    }
+
+    // One `button = inputs` per line, where the button is `up`, `down`,
+    // `left`, `right`, `a`, `b`, `start` or `select`, or one of them after
+    // `turbo_` for its turbo input, and the inputs are separated by spaces.
+    // Buttons that are not listed keep their default inputs, an empty list
+    // unbinds them.
+    //
+    //     ; lines starting with ';' are comments
+    //     a       = KeyX Enter PadEast
+    //     b       = KeyZ PadSouth
+    //     turbo_a = KeyS
+    pub fn parse(text: &str) -> io::Result<Bindings> {
+        let mut bindings = Bindings::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid binding on line {}: {}", number + 1, line));
+
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let inputs = value.split_whitespace().map(|name| name.to_string()).collect();
+            match key.trim().strip_prefix("turbo_") {
+                Some(key) => bindings.turbo[button_from_name(key).ok_or_else(invalid)? as usize] = inputs,
+                None => bindings.inputs[button_from_name(key.trim()).ok_or_else(invalid)? as usize] = inputs
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn inputs(&self, button: Button) -> &[String] {
+        &self.inputs[button as usize]
+    }
+
+    pub fn turbo_inputs(&self, button: Button) -> &[String] {
+        &self.turbo[button as usize]
+    }
+}
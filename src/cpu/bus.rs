@@ -0,0 +1,47 @@
+use super::interrupt::InterruptHandler;
+
+// Everything the CPU can see through its address pins. The CPU only talks to
+// the rest of the system through this trait, so it can be driven by the full
+// `Memory` map as well as by small test or recording buses.
+pub trait Bus {
+    fn read(&mut self, address: u16) -> u8;
+
+    fn write(&mut self, address: u16, data: u8);
+
+    // Advances every component attached to the bus by the given M-cycles.
+    fn tick(&mut self, cycles: u8);
+
+    // PPU dots per M-cycle, which halves in CGB double speed mode.
+    fn dots_per_cycle(&self) -> u32 {
+        4
+    }
+
+    // Executes STOP. Returns true if it switched the CGB CPU speed instead of
+    // entering low power mode.
+    fn stop(&mut self) -> bool {
+        false
+    }
+
+    // M-cycles the CPU has to wait for a transfer started by the last
+    // instruction or tick. Reading them clears them.
+    fn take_stall_cycles(&mut self) -> u16 {
+        0
+    }
+
+    fn read_interrupt_enable(&mut self) -> u8 {
+        self.read(0xFFFF)
+    }
+
+    fn read_interrupt_flag(&mut self) -> u8 {
+        self.read(0xFF0F)
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: InterruptHandler) {
+        let flag = self.read_interrupt_flag();
+        self.write(0xFF0F, flag & !(1 << interrupt as u8));
+    }
+
+    fn is_interrupt_pending(&mut self) -> bool {
+        self.read_interrupt_enable() & self.read_interrupt_flag() & 0x1F != 0
+    }
+}
@@ -0,0 +1,93 @@
+use std::io;
+
+use super::state::{SaveState, StateReader, StateWriter};
+
+// OAM DMA: copies 160 bytes to OAM, one per M-cycle, after a one cycle setup.
+#[derive(Clone)]
+pub struct OamDma {
+    source: u16,
+    // Next byte to copy, 160 when no transfer is running
+    index: u16,
+    // Source of a transfer written this M-cycle
+    pending: Option<u16>,
+    // Source of a transfer in its setup M-cycle, copying from the next one
+    setup: Option<u16>,
+    // Last byte put on the bus by the transfer
+    value: u8
+}
+
+impl OamDma {
+
+    pub fn new() -> OamDma {
+        OamDma { source: 0, index: 160, pending: None, setup: None, value: 0xFF }
+    }
+
+    pub fn start(&mut self, page: u8) {
+        let source = (page as u16) << 8;
+        // 0xE000 and above are decoded as work RAM, like echo RAM
+        let source = if source >= 0xE000 { source - 0x2000 } else { source };
+        // A running transfer keeps going during the setup of the new one
+        self.pending = Some(source);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.index < 160
+    }
+
+    pub fn source(&self) -> u16 {
+        self.source
+    }
+
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    // Advances by one M-cycle, returning the source address and OAM offset of
+    // the byte to copy in this cycle.
+    pub fn step(&mut self) -> Option<(u16, u8)> {
+        let transfer = if self.is_active() {
+            let index = self.index;
+            self.index += 1;
+            Some((self.source + index, index as u8))
+        } else {
+            None
+        };
+
+        if let Some(source) = self.setup.take() {
+            self.source = source;
+            self.index = 0;
+        }
+        self.setup = self.pending.take();
+
+        transfer
+    }
+
+    pub fn set_value(&mut self, value: u8) {
+        self.value = value;
+    }
+}
+
+impl SaveState for OamDma {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.source);
+        state.u16(self.index);
+        state.bool(self.pending.is_some());
+        state.u16(self.pending.unwrap_or(0));
+        state.bool(self.setup.is_some());
+        state.u16(self.setup.unwrap_or(0));
+        state.u8(self.value);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.source = state.u16()?;
+        self.index = state.u16()?;
+        let pending = state.bool()?;
+        let source = state.u16()?;
+        self.pending = pending.then_some(source);
+        let setup = state.bool()?;
+        let source = state.u16()?;
+        self.setup = setup.then_some(source);
+        self.value = state.u8()?;
+        Ok(())
+    }
+}
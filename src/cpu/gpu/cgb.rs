@@ -0,0 +1,226 @@
+use std::io;
+
+use super::GPU;
+use super::compat;
+use super::super::state::{SaveState, StateReader, StateWriter};
+
+// BG map attributes, stored in VRAM bank 1 at the same offset as the tile
+// index.
+#[derive(Clone, Copy, Default)]
+pub(super) struct TileAttributes(pub u8);
+
+impl TileAttributes {
+    pub fn palette(&self) -> u8 {
+        self.0 & 0b111
+    }
+
+    pub fn bank(&self) -> u8 {
+        (self.0 >> 3) & 1
+    }
+
+    pub fn x_flip(&self) -> bool {
+        (self.0 >> 5) & 1 == 1
+    }
+
+    pub fn y_flip(&self) -> bool {
+        (self.0 >> 6) & 1 == 1
+    }
+
+    // BG colours 1-3 are drawn over objects
+    pub fn priority(&self) -> bool {
+        (self.0 >> 7) & 1 == 1
+    }
+}
+
+// 8 palettes of 4 little-endian RGB555 colours, accessed through an index
+// register (BCPS/OCPS) and a data register (BCPD/OCPD).
+#[derive(Clone)]
+pub(super) struct ColorPalettes {
+    data: [u8; 64],
+    index: u8,
+    auto_increment: bool
+}
+
+impl ColorPalettes {
+    pub fn new() -> ColorPalettes {
+        ColorPalettes { data: [0xFF; 64], index: 0, auto_increment: false }
+    }
+
+    pub fn read_spec(&self) -> u8 {
+        (self.auto_increment as u8) << 7 | 0x40 | self.index
+    }
+
+    pub fn write_spec(&mut self, data: u8) {
+        self.auto_increment = (data >> 7) & 1 == 1;
+        self.index = data & 0x3F;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    pub fn write_data(&mut self, data: u8) {
+        self.data[self.index as usize] = data;
+        self.increment();
+    }
+
+    // The index still advances when a write is blocked by the PPU
+    pub fn increment(&mut self) {
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    pub fn color(&self, palette: u8, color_id: u8) -> u16 {
+        let index = (palette as usize * 4 + color_id as usize) * 2;
+        u16::from_le_bytes([self.data[index], self.data[index + 1]])
+    }
+}
+
+// Expands a 15-bit colour to 24 bits.
+pub(super) fn rgb555_to_rgb888(color: u16) -> (u8, u8, u8) {
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    (expand(color & 0x1F), expand((color >> 5) & 0x1F), expand((color >> 10) & 0x1F))
+}
+
+// Approximates how a colour looks on the CGB LCD, whose gamma is much darker
+// than a PC monitor's and whose channels bleed into each other.
+fn correct_color(color: u16) -> (u8, u8, u8) {
+    let channel = |shift: u16| (((color >> shift) & 0x1F) as f64 / 31.0).powf(4.0);
+    let (r, g, b) = (channel(0), channel(5), channel(10));
+
+    let output = |mixed: f64| ((mixed / 255.0).powf(1.0 / 2.2) * 255.0 * 255.0 / 280.0).round() as u8;
+    (
+        output(255.0 * r + 50.0 * g),
+        output(10.0 * r + 230.0 * g + 30.0 * b),
+        output(50.0 * r + 10.0 * g + 220.0 * b)
+    )
+}
+
+impl GPU {
+
+    pub fn set_cgb_mode(&mut self, cgb: bool) {
+        self.cgb = cgb;
+    }
+
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.corrected_colors = if enabled {
+            (0..0x8000).map(correct_color).collect()
+        } else {
+            Vec::new()
+        };
+    }
+
+    // DMG games on CGB hardware are drawn with palettes chosen by the boot
+    // ROM from the cartridge header.
+    pub fn use_compatibility_palettes(&mut self, header: &[u8]) {
+        self.compatibility_palettes = Some(compat::for_header(header));
+    }
+
+    pub(super) fn rgb(&self, color: u16) -> (u8, u8, u8) {
+        match self.corrected_colors.get(color as usize & 0x7FFF) {
+            Some(rgb) => *rgb,
+            None => rgb555_to_rgb888(color)
+        }
+    }
+
+    // FF4F - VBK: VRAM bank
+    pub fn read_vbk(&self) -> u8 {
+        0xFE | self.vram_bank
+    }
+
+    pub fn write_vbk(&mut self, data: u8) {
+        self.vram_bank = data & 1;
+    }
+
+    // FF68, FF69 - BCPS, BCPD: BG colour palette specification, data
+    pub fn read_bcps(&self) -> u8 {
+        self.bg_color_palettes.read_spec()
+    }
+
+    pub fn write_bcps(&mut self, data: u8) {
+        self.bg_color_palettes.write_spec(data);
+    }
+
+    pub fn read_bcpd(&self) -> u8 {
+        if self.vram_blocked() {
+            return 0xFF;
+        }
+        self.bg_color_palettes.read_data()
+    }
+
+    pub fn write_bcpd(&mut self, data: u8) {
+        if self.vram_blocked() {
+            self.bg_color_palettes.increment();
+        } else {
+            self.bg_color_palettes.write_data(data);
+        }
+    }
+
+    // FF6A, FF6B - OCPS, OCPD: OBJ colour palette specification, data
+    pub fn read_ocps(&self) -> u8 {
+        self.obj_color_palettes.read_spec()
+    }
+
+    pub fn write_ocps(&mut self, data: u8) {
+        self.obj_color_palettes.write_spec(data);
+    }
+
+    pub fn read_ocpd(&self) -> u8 {
+        if self.vram_blocked() {
+            return 0xFF;
+        }
+        self.obj_color_palettes.read_data()
+    }
+
+    pub fn write_ocpd(&mut self, data: u8) {
+        if self.vram_blocked() {
+            self.obj_color_palettes.increment();
+        } else {
+            self.obj_color_palettes.write_data(data);
+        }
+    }
+
+    // Tile index and attributes of entry `index` of the tile map selected by
+    // `map_bit` of LCDC.
+    pub(super) fn tile_map_entry(&self, map_bit: u8, index: u16) -> (u8, TileAttributes) {
+        let map: u16 = if (self.lcd_control >> map_bit) & 1 == 1 { 0x1C00 } else { 0x1800 };
+        let address = (map + index) as usize;
+        let attributes = if self.cgb { TileAttributes(self.vram[0x2000 + address]) } else { TileAttributes::default() };
+        (self.vram[address], attributes)
+    }
+
+    // Both bytes of row `line` of a BG/window tile, honouring the bank and
+    // Y-flip attributes.
+    pub(super) fn bg_tile_row(&self, tile_id: u8, attributes: TileAttributes, line: u8) -> (u8, u8) {
+        let tile_address = if (self.lcd_control >> 4) & 1 == 1 {
+            tile_id as u16 * 16
+        } else {
+            (0x1000 + (tile_id as i8 as i16 * 16)) as u16
+        };
+        let line = if attributes.y_flip() { 7 - line } else { line } as u16;
+        let address = attributes.bank() as usize * 0x2000 + (tile_address + line * 2) as usize;
+        (self.vram[address], self.vram[address + 1])
+    }
+
+    pub(super) fn cgb_bg_color(&self, palette: u8, color_id: u8) -> (u8, u8, u8) {
+        self.rgb(self.bg_color_palettes.color(palette, color_id))
+    }
+
+    pub(super) fn cgb_obj_color(&self, palette: u8, color_id: u8) -> (u8, u8, u8) {
+        self.rgb(self.obj_color_palettes.color(palette, color_id))
+    }
+}
+
+impl SaveState for ColorPalettes {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u8(self.read_spec());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        state.bytes(&mut self.data)?;
+        self.write_spec(state.u8()?);
+        Ok(())
+    }
+}
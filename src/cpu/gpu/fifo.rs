@@ -0,0 +1,368 @@
+use std::collections::VecDeque;
+use std::io;
+
+use super::{GPU, Sprite};
+use super::cgb::TileAttributes;
+use super::super::state::{invalid_data, SaveState, StateReader, StateWriter};
+
+// Dots spent on the discarded tile fetch at the start of every line.
+const STARTUP_DOTS: u8 = 6;
+const SPRITE_FETCH_DOTS: u8 = 6;
+
+#[derive(Clone, Copy)]
+enum FetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push
+}
+
+#[derive(Clone, Copy)]
+struct BgPixel {
+    color_id: u8,
+    attributes: TileAttributes
+}
+
+#[derive(Clone, Copy, Default)]
+struct SpritePixel {
+    color_id: u8,
+    obp1: bool,
+    bg_priority: bool,
+    cgb_palette: u8,
+    oam_index: u8
+}
+
+#[derive(Clone)]
+pub struct PixelFifo {
+    background: VecDeque<BgPixel>,
+    sprite: VecDeque<SpritePixel>,
+
+    step: FetcherStep,
+    step_dots: u8,
+    fetcher_x: u8,
+    tile_id: u8,
+    tile_attributes: TileAttributes,
+    data_low: u8,
+    data_high: u8,
+
+    startup_dots: u8,
+    discard: u8,
+    lx: u8,
+    dots: u16,
+
+    window: bool,
+
+    sprites: Vec<Sprite>,
+    sprite_fetch: Option<(usize, u8)>
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            background: VecDeque::with_capacity(16),
+            sprite: VecDeque::with_capacity(8),
+            step: FetcherStep::Tile,
+            step_dots: 0,
+            fetcher_x: 0,
+            tile_id: 0,
+            tile_attributes: TileAttributes::default(),
+            data_low: 0,
+            data_high: 0,
+            startup_dots: 0,
+            discard: 0,
+            lx: 0,
+            dots: 0,
+            window: false,
+            sprites: Vec::with_capacity(10),
+            sprite_fetch: None
+        }
+    }
+
+    // Dots spent in mode 3 on the current line.
+    pub fn dots(&self) -> u16 {
+        self.dots
+    }
+
+    fn restart_fetcher(&mut self) {
+        self.step = FetcherStep::Tile;
+        self.step_dots = 0;
+    }
+}
+
+impl GPU {
+
+    // Mode 2
+    pub(super) fn fifo_oam_scan(&mut self) {
+        self.fifo.sprites = self.oam_scan();
+    }
+
+    pub(super) fn fifo_start_line(&mut self) {
+        let fifo = &mut self.fifo;
+        fifo.background.clear();
+        fifo.sprite.clear();
+        fifo.restart_fetcher();
+        fifo.fetcher_x = 0;
+        fifo.startup_dots = STARTUP_DOTS;
+        fifo.discard = self.scx & 7;
+        fifo.lx = 0;
+        fifo.dots = 0;
+        fifo.window = false;
+        fifo.sprite_fetch = None;
+    }
+
+    // Advances mode 3 by one dot. Returns true once the 160th pixel of the
+    // line has been pushed to the LCD.
+    pub(super) fn fifo_tick(&mut self) -> bool {
+        self.fifo.dots += 1;
+
+        if self.fifo.startup_dots > 0 {
+            self.fifo.startup_dots -= 1;
+            return false;
+        }
+
+        if self.fifo.sprite_fetch.is_none() && self.fifo.discard == 0 && (self.lcd_control >> 1) & 1 == 1 {
+            let lx = self.fifo.lx as u16;
+            // Lower X is fetched first so it keeps priority over overlapping
+            // objects fetched later.
+            self.fifo.sprite_fetch = self.fifo.sprites.iter()
+                .enumerate()
+                .filter(|(_, s)| s.x as u16 <= lx + 8)
+                .min_by_key(|(_, s)| s.x)
+                .map(|(index, _)| (index, SPRITE_FETCH_DOTS));
+        }
+
+        if let Some((index, dots)) = self.fifo.sprite_fetch {
+            // The background fetch in progress has to complete before the
+            // fetcher can be borrowed for the object.
+            if !matches!(self.fifo.step, FetcherStep::Push) || self.fifo.background.is_empty() {
+                self.fetcher_tick();
+            } else if dots > 1 {
+                self.fifo.sprite_fetch = Some((index, dots - 1));
+            } else {
+                let sprite = self.fifo.sprites.remove(index);
+                self.merge_sprite(sprite);
+                self.fifo.sprite_fetch = None;
+            }
+            return false;
+        }
+
+        self.fetcher_tick();
+        self.shift_pixel()
+    }
+
+    fn fetcher_tick(&mut self) {
+        match self.fifo.step {
+            FetcherStep::Push => {
+                if self.fifo.background.is_empty() {
+                    let attributes = self.fifo.tile_attributes;
+                    for pixel in 0..8 {
+                        let bit = if attributes.x_flip() { pixel } else { 7 - pixel };
+                        let color_id = ((self.fifo.data_high >> bit) & 1) << 1 | ((self.fifo.data_low >> bit) & 1);
+                        self.fifo.background.push_back(BgPixel { color_id, attributes });
+                    }
+                    self.fifo.fetcher_x = self.fifo.fetcher_x.wrapping_add(1);
+                    self.fifo.restart_fetcher();
+                }
+            },
+            step => {
+                self.fifo.step_dots += 1;
+                if self.fifo.step_dots < 2 {
+                    return;
+                }
+                self.fifo.step_dots = 0;
+
+                match step {
+                    FetcherStep::Tile => {
+                        (self.fifo.tile_id, self.fifo.tile_attributes) = self.fetch_tile_id();
+                        self.fifo.step = FetcherStep::DataLow;
+                    },
+                    FetcherStep::DataLow => {
+                        self.fifo.data_low = self.fetch_tile_data().0;
+                        self.fifo.step = FetcherStep::DataHigh;
+                    },
+                    FetcherStep::DataHigh => {
+                        self.fifo.data_high = self.fetch_tile_data().1;
+                        self.fifo.step = FetcherStep::Push;
+                    },
+                    FetcherStep::Push => unreachable!()
+                }
+            }
+        }
+    }
+
+    fn fetcher_y(&self) -> u8 {
+        if self.fifo.window {
+            self.window_line
+        } else {
+            self.scy.wrapping_add(self.ly)
+        }
+    }
+
+    fn fetch_tile_id(&self) -> (u8, TileAttributes) {
+        let (map_bit, x) = if self.fifo.window {
+            (6, self.fifo.fetcher_x & 31)
+        } else {
+            (3, ((self.scx >> 3).wrapping_add(self.fifo.fetcher_x)) & 31)
+        };
+        let y = (self.fetcher_y() / 8) as u16;
+
+        self.tile_map_entry(map_bit, y * 32 + x as u16)
+    }
+
+    fn fetch_tile_data(&self) -> (u8, u8) {
+        self.bg_tile_row(self.fifo.tile_id, self.fifo.tile_attributes, self.fetcher_y() % 8)
+    }
+
+    fn merge_sprite(&mut self, sprite: Sprite) {
+        let (data_low, data_high) = self.sprite_tile_row(&sprite);
+
+        while self.fifo.sprite.len() < 8 {
+            self.fifo.sprite.push_back(SpritePixel::default());
+        }
+
+        for pixel in 0..8u16 {
+            let x = sprite.x as u16 + pixel;
+            if x < 8 + self.fifo.lx as u16 {
+                continue;
+            }
+            let slot = (x - 8 - self.fifo.lx as u16) as usize;
+
+            let color_id = sprite.color_id(data_low, data_high, pixel as u8);
+
+            // Objects fetched earlier keep their opaque pixels, unless in CGB
+            // mode the new one comes first in OAM.
+            let current = self.fifo.sprite[slot];
+            if current.color_id == 0 || (self.cgb && color_id != 0 && sprite.index < current.oam_index) {
+                self.fifo.sprite[slot] = SpritePixel {
+                    color_id,
+                    obp1: sprite.obp1(),
+                    bg_priority: sprite.bg_priority(),
+                    cgb_palette: sprite.cgb_palette(),
+                    oam_index: sprite.index
+                };
+            }
+        }
+    }
+
+    fn shift_pixel(&mut self) -> bool {
+        if !self.fifo.window && self.fifo.discard == 0 {
+            if let Some((start_x, skipped)) = self.window_start() {
+                if self.fifo.lx >= start_x {
+                    self.fifo.window = true;
+                    self.fifo.discard = skipped;
+                    self.window_drawn = true;
+                    self.fifo.background.clear();
+                    self.fifo.fetcher_x = 0;
+                    self.fifo.restart_fetcher();
+                    return false;
+                }
+            }
+        }
+
+        let Some(bg) = self.fifo.background.pop_front() else {
+            return false;
+        };
+
+        if self.fifo.discard > 0 {
+            self.fifo.discard -= 1;
+            return false;
+        }
+
+        let sprite = self.fifo.sprite.pop_front().unwrap_or_default();
+        // In CGB mode LCDC.0 only affects priority
+        let bg_color_id = if self.lcd_control & 1 == 1 || self.cgb { bg.color_id } else { 0 };
+
+        let object_visible = sprite.color_id != 0 && (self.lcd_control >> 1) & 1 == 1;
+        let color = if object_visible && self.object_wins(bg_color_id, bg.attributes, sprite.bg_priority) {
+            if self.cgb {
+                self.cgb_obj_color(sprite.cgb_palette, sprite.color_id)
+            } else {
+                self.obj_color(sprite.obp1, sprite.color_id)
+            }
+        } else if self.cgb {
+            self.cgb_bg_color(bg.attributes.palette(), bg_color_id)
+        } else {
+            self.bg_color(bg_color_id)
+        };
+
+        let lx = self.fifo.lx;
+        self.set_pixel(lx, color);
+        self.fifo.lx += 1;
+
+        self.fifo.lx == 160
+    }
+}
+
+impl SaveState for PixelFifo {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.background.len() as u8);
+        for pixel in &self.background {
+            state.bytes(&[pixel.color_id, pixel.attributes.0]);
+        }
+        state.u8(self.sprite.len() as u8);
+        for pixel in &self.sprite {
+            state.bytes(&[pixel.color_id, pixel.obp1 as u8, pixel.bg_priority as u8, pixel.cgb_palette, pixel.oam_index]);
+        }
+
+        state.u8(self.step as u8);
+        state.bytes(&[self.step_dots, self.fetcher_x, self.tile_id, self.tile_attributes.0, self.data_low, self.data_high]);
+        state.bytes(&[self.startup_dots, self.discard, self.lx]);
+        state.u16(self.dots);
+        state.bool(self.window);
+
+        state.u8(self.sprites.len() as u8);
+        for sprite in &self.sprites {
+            state.bytes(&[sprite.y, sprite.x, sprite.tile_index, sprite.attributes, sprite.index]);
+        }
+        let (index, dots) = self.sprite_fetch.unwrap_or((0xFF, 0));
+        state.bytes(&[index as u8, dots]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.background.clear();
+        for _ in 0..state.u8()? {
+            let (color_id, attributes) = (state.u8()?, state.u8()?);
+            self.background.push_back(BgPixel { color_id, attributes: TileAttributes(attributes) });
+        }
+        self.sprite.clear();
+        for _ in 0..state.u8()? {
+            let mut pixel = [0; 5];
+            state.bytes(&mut pixel)?;
+            let [color_id, obp1, bg_priority, cgb_palette, oam_index] = pixel;
+            self.sprite.push_back(SpritePixel { color_id, obp1: obp1 != 0, bg_priority: bg_priority != 0, cgb_palette, oam_index });
+        }
+
+        self.step = match state.u8()? {
+            0 => FetcherStep::Tile,
+            1 => FetcherStep::DataLow,
+            2 => FetcherStep::DataHigh,
+            3 => FetcherStep::Push,
+            _ => return Err(invalid_data("Invalid pixel fetcher step"))
+        };
+        let mut fetcher = [0; 6];
+        state.bytes(&mut fetcher)?;
+        let tile_attributes;
+        [self.step_dots, self.fetcher_x, self.tile_id, tile_attributes, self.data_low, self.data_high] = fetcher;
+        self.tile_attributes = TileAttributes(tile_attributes);
+        self.startup_dots = state.u8()?;
+        self.discard = state.u8()?;
+        self.lx = state.u8()?.min(160);
+        self.dots = state.u16()?;
+        self.window = state.bool()?;
+
+        self.sprites.clear();
+        for _ in 0..state.u8()? {
+            let mut sprite = [0; 5];
+            state.bytes(&mut sprite)?;
+            let [y, x, tile_index, attributes, index] = sprite;
+            self.sprites.push(Sprite { y, x, tile_index, attributes, index });
+        }
+        let (index, dots) = (state.u8()? as usize, state.u8()?);
+        self.sprite_fetch = match index {
+            0xFF => None,
+            index if index < self.sprites.len() => Some((index, dots)),
+            _ => return Err(invalid_data("Invalid object fetch"))
+        };
+        Ok(())
+    }
+}
@@ -0,0 +1,754 @@
+mod cgb;
+mod compat;
+mod fifo;
+mod palette;
+
+use core::panic;
+use std::io;
+
+use cgb::{ColorPalettes, TileAttributes};
+use fifo::PixelFifo;
+use super::state::{SaveState, StateReader, StateWriter};
+pub use palette::{Palette, Palettes};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Renderer {
+    // Draws a whole line at the end of mode 3, which always lasts 172 dots.
+    Scanline,
+    // Pushes pixels dot by dot, so mode 3 length and mid-line register
+    // writes behave as on hardware.
+    Fifo
+}
+
+#[derive(Clone, Copy)]
+struct Sprite {
+    y: u8,
+    x: u8,
+    tile_index: u8,
+    attributes: u8,
+    // Position in OAM, which decides priority in CGB mode
+    index: u8
+}
+
+impl Sprite {
+    fn bg_priority(&self) -> bool {
+        (self.attributes >> 7) & 1 == 1
+    }
+
+    fn y_flip(&self) -> bool {
+        (self.attributes >> 6) & 1 == 1
+    }
+
+    fn x_flip(&self) -> bool {
+        (self.attributes >> 5) & 1 == 1
+    }
+
+    fn obp1(&self) -> bool {
+        (self.attributes >> 4) & 1 == 1
+    }
+
+    fn vram_bank(&self) -> u8 {
+        (self.attributes >> 3) & 1
+    }
+
+    fn cgb_palette(&self) -> u8 {
+        self.attributes & 0b111
+    }
+
+    // Colour of the `pixel`-th column (from the left) of a row of this object.
+    fn color_id(&self, data_low: u8, data_high: u8, pixel: u8) -> u8 {
+        let bit = if self.x_flip() { pixel } else { 7 - pixel };
+        ((data_high >> bit) & 1) << 1 | ((data_low >> bit) & 1)
+    }
+}
+
+#[derive(Clone)]
+pub struct GPU {
+
+    pub framebuffer: [u8; 160*144*4],
+    // Both CGB banks, bank 1 is only used in CGB mode
+    vram: [u8; 0x4000],
+    oam: [u8; 0x00A0],
+    scanline_counter: u16,
+    frames: u64,
+
+    renderer: Renderer,
+    palettes: Palettes,
+    fifo: PixelFifo,
+    mode3_length: u16,
+    // Whether CPU accesses to VRAM and OAM are restricted by the PPU mode
+    access_blocking: bool,
+    // OAM DMA in progress, OAM is unavailable to the CPU
+    dma_active: bool,
+    // Whether pixels are drawn at all, turned off to skip frames when
+    // fast-forwarding
+    rendering: bool,
+
+    cgb: bool,
+    // FF4F - VBK: VRAM bank
+    vram_bank: u8,
+    bg_color_palettes: ColorPalettes,
+    obj_color_palettes: ColorPalettes,
+    // RGB888 for every RGB555 colour, empty without colour correction
+    corrected_colors: Vec<(u8, u8, u8)>,
+    compatibility_palettes: Option<compat::CompatibilityPalettes>,
+
+    // Window line counter, only advanced on lines where the window is drawn
+    window_line: u8,
+    window_drawn: bool,
+    // Set once LY == WY has been seen during the current frame
+    window_y_triggered: bool,
+    window_wrap: bool,
+
+    // LY has wrapped to 0 early during line 153
+    last_line: bool,
+    stat_interrupt_line: bool,
+
+    // The first line after enabling the LCD skips the OAM scan and is shorter
+    first_line: bool,
+    // Nothing is shown while the LCD is off, nor for the first frame after
+    // turning it back on
+    frame_blank: bool,
+    skip_frame: bool,
+
+    // FF40 - LCDC: LCD control
+    lcd_control: u8,
+    // FF41 - STAT: LCD status
+    lcd_status: u8,
+    // FF42,FF43 - SCY, SCX: Background viewport y,x position
+    scy: u8,
+    scx: u8,
+    // FF44 - LY: LCD Y coordinate
+    ly: u8,
+    // FF45 - LYC: LY compare
+    lyc: u8,
+    // FF46 - DMA: OAM DMA source address & start
+    dma: u8,
+    // FF47 - BGP: BG palette data
+    bgp: u8,
+    // FF48, FF49 - OBP0, OBP1: OBJ palette 0, 1 data
+    obp0: u8,
+    obp1: u8,
+    // FF4A, FF4B - WY, WX: Window y,x position plus 7
+    wy: u8,
+    wx: u8
+}
+
+impl GPU {
+    pub fn new() -> GPU {
+
+        GPU { framebuffer: [0xFF; 160*144*4], vram: [0; 0x4000], oam: [0; 0x00A0], scanline_counter: 0, frames: 0, renderer: Renderer::Scanline, palettes: Palettes::new(Palette::GREY), fifo: PixelFifo::new(), mode3_length: 172, access_blocking: true, dma_active: false, rendering: true, cgb: false, vram_bank: 0, bg_color_palettes: ColorPalettes::new(), obj_color_palettes: ColorPalettes::new(), corrected_colors: Vec::new(), compatibility_palettes: None, window_line: 0, window_drawn: false, window_y_triggered: false, window_wrap: false, last_line: false, stat_interrupt_line: false, first_line: false, frame_blank: true, skip_frame: false, lcd_control: 0, lcd_status: 0, scy: 0, scx: 0, ly: 0, lyc: 0, dma: 0, bgp: 0, obp0: 0, obp1: 0, wy: 0, wx: 0 }
+    }
+
+    // Whether the LCD currently shows a blank (white) screen instead of the
+    // framebuffer.
+    pub fn is_frame_blank(&self) -> bool {
+        self.frame_blank
+    }
+
+    // Tile data of the first 256 tiles of the BG map, row by row as shown on
+    // screen, which is how SGB VRAM transfers receive their 4 KiB.
+    pub fn screen_tile_data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(0x1000);
+        for tile in 0..256 {
+            let (tile_id, attributes) = self.tile_map_entry(3, (tile / 20) * 32 + tile % 20);
+            for line in 0..8 {
+                let (low, high) = self.bg_tile_row(tile_id, attributes, line);
+                data.extend_from_slice(&[low, high]);
+            }
+        }
+        data
+    }
+
+    // Colour shown while the frame is blank.
+    pub fn blank_color(&self) -> (u8, u8, u8) {
+        if self.cgb {
+            self.rgb(0x7FFF)
+        } else {
+            self.bg_shade_color(0)
+        }
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.palettes = palettes;
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.renderer = renderer;
+    }
+
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.rendering = enabled;
+    }
+
+    pub fn read_lcd_control(&self) -> u8 {
+        self.lcd_control
+    }
+
+    pub fn write_lcd_control(&mut self, data: u8) {
+        let was_enabled = self.lcd_control >> 7 == 1;
+        self.lcd_control = data;
+
+        match (was_enabled, data >> 7 == 1) {
+            (true, false) => {
+                // LY and the mode read 0 while the LCD is off
+                self.scanline_counter = 0;
+                self.ly = 0;
+                self.lcd_status &= !0b11;
+                self.last_line = false;
+                self.stat_interrupt_line = false;
+                self.window_line = 0;
+                self.window_drawn = false;
+                self.window_y_triggered = false;
+                self.window_wrap = false;
+                self.frame_blank = true;
+            },
+            (false, true) => {
+                self.scanline_counter = 0;
+                self.first_line = true;
+                self.skip_frame = true;
+                self.compare_ly();
+            },
+            _ => {}
+        }
+    }
+
+    pub fn read_scy(&self) -> u8 {
+        self.scy
+    }
+
+    pub fn write_scy(&mut self, data: u8) {
+        self.scy = data;
+    }
+
+    pub fn write_scx(&mut self, data: u8) {
+        self.scx = data;
+    }
+
+    pub fn write_obp0(&mut self, data: u8) {
+        self.obp0 = data;
+    }
+
+    pub fn write_obp1(&mut self, data: u8) {
+        self.obp1 = data;
+    }
+
+    pub fn write_bgp(&mut self, data: u8) {
+        self.bgp = data;
+    }
+
+    pub fn read_ly(&self) -> u8 {
+        self.ly
+    }
+
+    pub fn read_lcd_status(&self) -> u8 {
+        self.lcd_status | 0x80
+    }
+
+    // Returns true if the write raises the STAT interrupt, either through the
+    // glitch or by enabling a source whose condition already holds.
+    pub fn write_lcd_status(&mut self, data: u8) -> bool {
+        // DMG bug: for one cycle STAT behaves as if all the sources were
+        // enabled, so writing during HBlank, VBlank or LY=LYC fires the
+        // interrupt. The CGB does not have it.
+        let glitch = !self.cgb && self.lcd_control >> 7 == 1 && self.stat_line(0b01011000) && !self.stat_interrupt_line;
+
+        let old_line = self.stat_interrupt_line;
+        self.lcd_status = (data & 0b01111000) | (self.lcd_status & 0b111);
+        self.stat_interrupt_line = self.lcd_control >> 7 == 1 && self.stat_line(self.lcd_status);
+        glitch || (self.stat_interrupt_line && !old_line)
+    }
+
+    pub fn read_lyc(&self) -> u8 {
+        self.lyc
+    }
+
+    // Returns true if the write raises the STAT interrupt.
+    pub fn write_lyc(&mut self, data: u8) -> bool {
+        self.lyc = data;
+        if self.lcd_control >> 7 == 0 {
+            return false;
+        }
+        self.compare_ly();
+        self.update_stat_line(false)
+    }
+
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.access_blocking = enabled;
+    }
+
+    fn mode(&self) -> u8 {
+        self.lcd_status & 0b11
+    }
+
+    // The PPU owns VRAM while drawing pixels
+    fn vram_blocked(&self) -> bool {
+        self.access_blocking && self.lcd_control >> 7 == 1 && self.mode() == 0b11
+    }
+
+    // The PPU owns OAM during OAM scan and while drawing pixels
+    pub fn oam_blocked(&self) -> bool {
+        self.dma_active || (self.access_blocking && self.lcd_control >> 7 == 1 && self.mode() >= 0b10)
+    }
+
+    pub fn read_vram(&self, address: u16) -> u8 {
+        if self.vram_blocked() {
+            return 0xFF;
+        }
+        self.vram[self.vram_bank as usize * 0x2000 + address as usize]
+    }
+
+    pub fn write_vram(&mut self, address: u16, data: u8) {
+        if self.vram_blocked() {
+            return;
+        }
+        self.vram[self.vram_bank as usize * 0x2000 + address as usize] = data;
+    }
+
+    pub fn read_oam(&self, address: u16) -> u8 {
+        if self.oam_blocked() {
+            return 0xFF;
+        }
+        self.oam[address as usize]
+    }
+
+    pub fn write_oam(&mut self, address: u16, data: u8) {
+        if self.oam_blocked() {
+            return;
+        }
+        self.oam[address as usize] = data;
+    }
+
+    pub fn read_dma(&self) -> u8 {
+        self.dma
+    }
+
+    pub fn write_dma(&mut self, data: u8) {
+        self.dma = data;
+    }
+
+    pub fn write_wy(&mut self, data: u8) {
+        self.wy = data;
+    }
+
+    pub fn write_wx(&mut self, data: u8) {
+        self.wx = data;
+    }
+
+    // OAM DMA writes bypass the PPU mode restrictions.
+    pub fn write_oam_dma(&mut self, index: u8, data: u8) {
+        self.oam[index as usize] = data;
+    }
+
+    // VRAM DMA writes to the selected bank, regardless of the PPU mode.
+    pub fn write_vram_dma(&mut self, address: u16, data: u8) {
+        self.vram[self.vram_bank as usize * 0x2000 + address as usize] = data;
+    }
+
+    pub fn set_dma_active(&mut self, active: bool) {
+        self.dma_active = active;
+    }
+
+    pub fn update(&mut self, dots: u16) -> (bool, bool) {
+        let mut request_vblank = false;
+        let mut vblank_started = false;
+
+        if self.lcd_control >> 7 == 0 {
+            return (false, false)
+        }
+
+        self.scanline_counter += dots;
+
+        match self.lcd_status & 0b11 {
+            0b00 if self.first_line => {
+                // Line 0 after enabling the LCD starts in mode 0 instead of
+                // mode 2, and enters mode 3 slightly early
+                if self.scanline_counter >= 76 {
+                    self.scanline_counter -= 76;
+                    self.first_line = false;
+                    self.start_drawing();
+                }
+            },
+            0b00 => {
+                // In HBLANK
+                let hblank_length = 376u16.saturating_sub(self.mode3_length);
+                if self.scanline_counter >= hblank_length {
+                    self.scanline_counter -= hblank_length;
+                    self.ly += 1;
+                    self.end_window_line();
+
+                    if self.ly >= 144 {
+                        self.lcd_status = (self.lcd_status & !0b11) | 0b01;
+                        request_vblank = true;
+                        vblank_started = true;
+                        self.frames += 1;
+                        self.frame_blank = self.skip_frame;
+                        self.skip_frame = false;
+                    } else {
+                        self.start_oam_scan();
+                    }
+                }
+            },
+            0b01 => {
+                // In VBLANK
+                // LY already reads 0 after the first M-cycle of line 153
+                if self.ly == 153 && self.scanline_counter >= 4 {
+                    self.ly = 0;
+                    self.last_line = true;
+                }
+
+                if self.scanline_counter >= 456 {
+                    self.scanline_counter -= 456;
+                    if self.last_line {
+                        self.last_line = false;
+                        self.window_line = 0;
+                        self.window_y_triggered = false;
+                        self.window_wrap = false;
+                        self.start_oam_scan();
+                    } else {
+                        self.ly += 1;
+                    }
+                }
+            },
+            0b10 => {
+                // In OAM Scan
+                if self.scanline_counter >= 80 {
+                    self.scanline_counter -= 80;
+                    self.start_drawing();
+                }
+            },
+            0b11 => {
+                // Drawing pixels
+                let finished = match self.renderer {
+                    Renderer::Scanline => {
+                        if self.scanline_counter >= 172 {
+                            self.scanline_counter -= 172;
+                            self.mode3_length = 172;
+                            self.scan_line();
+                            true
+                        } else {
+                            false
+                        }
+                    },
+                    Renderer::Fifo => {
+                        let mut finished = false;
+                        while self.scanline_counter > 0 && !finished {
+                            self.scanline_counter -= 1;
+                            finished = self.fifo_tick();
+                        }
+                        self.mode3_length = self.fifo.dots();
+                        finished
+                    }
+                };
+
+                if finished {
+                    self.lcd_status &= !0b11;
+                }
+            }
+            _ => panic!("Unexpected LCD status: {}", self.lcd_status)
+        }
+
+        self.compare_ly();
+
+        // The mode 2 source also fires when entering VBlank on DMG
+        let oam_at_vblank = vblank_started && (self.lcd_status >> 5) & 1 == 1;
+        let request_lcd = self.update_stat_line(oam_at_vblank);
+
+        (request_vblank, request_lcd)
+    }
+
+    fn start_drawing(&mut self) {
+        self.lcd_status = (self.lcd_status & !0b11) | 0b11;
+        if self.renderer == Renderer::Fifo {
+            self.fifo_oam_scan();
+            self.fifo_start_line();
+        }
+    }
+
+    fn compare_ly(&mut self) {
+        if self.ly == self.lyc {
+            self.lcd_status |= 0b100;
+        } else {
+            self.lcd_status &= !0b100;
+        }
+    }
+
+    // State of the STAT interrupt line for the given source enable bits.
+    fn stat_line(&self, enables: u8) -> bool {
+        let mode = self.mode();
+
+        ((enables >> 6) & 1 == 1 && self.lcd_status & 0b100 != 0)
+            || ((enables >> 5) & 1 == 1 && mode == 0b10)
+            || ((enables >> 4) & 1 == 1 && mode == 0b01)
+            || ((enables >> 3) & 1 == 1 && mode == 0b00)
+    }
+
+    // All STAT sources are OR'ed into a single line and the interrupt is only
+    // requested on its rising edge, so a source becoming active while another
+    // one already holds the line high is "blocked".
+    fn update_stat_line(&mut self, force: bool) -> bool {
+        let line = force || self.stat_line(self.lcd_status);
+        let rising = line && !self.stat_interrupt_line;
+        self.stat_interrupt_line = line;
+        rising
+    }
+
+    fn sprite_height(&self) -> u16 {
+        if (self.lcd_control >> 2) & 1 == 1 { 16 } else { 8 }
+    }
+
+    // Selects the first 10 objects in OAM order that overlap LY.
+    fn oam_scan(&self) -> Vec<Sprite> {
+        let height = self.sprite_height();
+        let line = self.ly as u16 + 16;
+
+        self.oam.chunks_exact(4)
+            .enumerate()
+            .filter(|(_, obj)| line >= obj[0] as u16 && line < obj[0] as u16 + height)
+            .take(10)
+            .map(|(index, obj)| Sprite { y: obj[0], x: obj[1], tile_index: obj[2], attributes: obj[3], index: index as u8 })
+            .collect()
+    }
+
+    // Tile data of the row of `sprite` displayed on LY. In 8x16 mode bit 0 of
+    // the tile index is ignored, and Y-flip swaps the two tiles as well.
+    fn sprite_tile_row(&self, sprite: &Sprite) -> (u8, u8) {
+        let height = self.sprite_height();
+
+        let mut line = (self.ly as u16 + 16).wrapping_sub(sprite.y as u16) & (height - 1);
+        if sprite.y_flip() {
+            line = height - 1 - line;
+        }
+
+        let tile_index = if height == 16 { sprite.tile_index & 0xFE } else { sprite.tile_index } as u16;
+        let bank = if self.cgb { sprite.vram_bank() as usize } else { 0 };
+        let address = bank * 0x2000 + (tile_index * 16 + line * 2) as usize;
+        (self.vram[address], self.vram[address + 1])
+    }
+
+    fn end_window_line(&mut self) {
+        if self.window_drawn {
+            self.window_line = self.window_line.wrapping_add(1);
+            self.window_drawn = false;
+        }
+        self.window_wrap = self.window_visible() && self.wx == 166;
+    }
+
+    fn start_oam_scan(&mut self) {
+        self.lcd_status = (self.lcd_status & !0b11) | 0b10;
+        if self.ly == self.wy {
+            self.window_y_triggered = true;
+        }
+    }
+
+    fn window_visible(&self) -> bool {
+        (self.lcd_control >> 5) & 1 == 1 && self.window_y_triggered
+    }
+
+    // First screen X covered by the window on this line, and how many window
+    // pixels are scrolled off its left edge.
+    fn window_start(&self) -> Option<(u8, u8)> {
+        if !self.window_visible() {
+            return None;
+        }
+
+        match self.wx {
+            // WX=166 on the previous line keeps the window on for this whole line
+            _ if self.window_wrap => Some((0, 0)),
+            // WX=0 also swallows the SCX fine scroll, making the window stutter
+            0 => Some((0, 7 + (self.scx & 7))),
+            1..=6 => Some((0, 7 - self.wx)),
+            7..=166 => Some((self.wx - 7, 0)),
+            _ => None
+        }
+    }
+
+    fn bg_color(&self, color_id: u8) -> (u8, u8, u8) {
+        self.bg_shade_color((self.bgp >> (color_id * 2)) & 0b11)
+    }
+
+    fn bg_shade_color(&self, shade: u8) -> (u8, u8, u8) {
+        match self.compatibility_palettes {
+            Some(palettes) => self.rgb(palettes[0][shade as usize]),
+            None => self.palettes.bg.color(shade)
+        }
+    }
+
+    // Whether an opaque object pixel is drawn over the BG/window pixel.
+    fn object_wins(&self, bg_color_id: u8, bg_attributes: TileAttributes, sprite_bg_priority: bool) -> bool {
+        if bg_color_id == 0 {
+            return true;
+        }
+        if self.cgb {
+            // In CGB mode LCDC.0 clear gives objects priority over everything
+            self.lcd_control & 1 == 0 || !(sprite_bg_priority || bg_attributes.priority())
+        } else {
+            !sprite_bg_priority
+        }
+    }
+
+    fn obj_color(&self, obp1: bool, color_id: u8) -> (u8, u8, u8) {
+        let palette = if obp1 { self.obp1 } else { self.obp0 };
+        let shade = (palette >> (color_id * 2)) & 0b11;
+        match (self.compatibility_palettes, obp1) {
+            (Some(palettes), _) => self.rgb(palettes[1 + obp1 as usize][shade as usize]),
+            (None, true) => self.palettes.obp1.color(shade),
+            (None, false) => self.palettes.obp0.color(shade)
+        }
+    }
+
+    fn set_pixel(&mut self, x: u8, (r, g, b): (u8, u8, u8)) {
+        if !self.rendering {
+            return;
+        }
+        let index = (self.ly as usize * 160 * 4) + (x as usize * 4);
+        self.framebuffer[index..index + 4].copy_from_slice(&[r, g, b, 0xFF]);
+    }
+
+    fn scan_line(&mut self) {
+
+        let mut bg_colors = [0u8; 160];
+        let mut bg_attributes = [TileAttributes::default(); 160];
+
+        // Draw Background and Window. In CGB mode LCDC.0 only affects priority.
+        if self.lcd_control & 1 == 1 || self.cgb {
+
+            let window_start = self.window_start();
+
+            for p in 0u8..160 {
+                let (map_bit, x_tilemap, y_tilemap) = match window_start {
+                    Some((start_x, skipped)) if p >= start_x => {
+                        self.window_drawn = true;
+                        (6, p - start_x + skipped, self.window_line)
+                    },
+                    _ => (3, p.wrapping_add(self.scx), self.scy.wrapping_add(self.ly))
+                };
+
+                let map_index = (y_tilemap as u16 / 8) * 32 + (x_tilemap as u16 / 8);
+                let (tile_id, attributes) = self.tile_map_entry(map_bit, map_index);
+                let (data_1, data_2) = self.bg_tile_row(tile_id, attributes, y_tilemap % 8);
+
+                let mask = if attributes.x_flip() { x_tilemap % 8 } else { 7 - (x_tilemap % 8) };
+
+                let color_id = ((data_2 >> mask) & 1) << 1 | ((data_1 >> mask) & 1);
+
+                bg_colors[p as usize] = color_id;
+                bg_attributes[p as usize] = attributes;
+
+                let color = if self.cgb {
+                    self.cgb_bg_color(attributes.palette(), color_id)
+                } else {
+                    self.bg_color(color_id)
+                };
+                self.set_pixel(p, color);
+            }
+        } else {
+            for p in 0u8..160 {
+                self.set_pixel(p, self.bg_shade_color(0));
+            }
+        }
+
+        // Draw sprites
+        if (self.lcd_control >> 1) & 1 == 1 {
+
+            // On DMG the object with the smallest X wins, ties going to the
+            // one that comes first in OAM. On CGB only the OAM order counts.
+            let mut sprites = self.oam_scan();
+            if !self.cgb {
+                sprites.sort_by_key(|s| s.x);
+            }
+
+            let rows: Vec<(u8, u8)> = sprites.iter().map(|s| self.sprite_tile_row(s)).collect();
+
+            for p in 0u8..160 {
+                let x = p as u16 + 8;
+
+                for (sprite, (data_low, data_high)) in sprites.iter().zip(rows.iter()) {
+                    if x < sprite.x as u16 || x >= sprite.x as u16 + 8 {
+                        continue;
+                    }
+
+                    let color_id = sprite.color_id(*data_low, *data_high, (x - sprite.x as u16) as u8);
+                    if color_id == 0 {
+                        continue;
+                    }
+
+                    if self.object_wins(bg_colors[p as usize], bg_attributes[p as usize], sprite.bg_priority()) {
+                        let color = if self.cgb {
+                            self.cgb_obj_color(sprite.cgb_palette(), color_id)
+                        } else {
+                            self.obj_color(sprite.obp1(), color_id)
+                        };
+                        self.set_pixel(p, color);
+                    }
+                    break;
+                }
+            }
+        }
+    }
+}
+
+// The renderer, DMG palettes and colour options are settings rather than
+// machine state, so they are left as they are.
+impl SaveState for GPU {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&self.framebuffer);
+        state.bytes(&self.vram);
+        state.bytes(&self.oam);
+        state.u16(self.scanline_counter);
+        state.u64(self.frames);
+        self.fifo.save_state(state);
+        state.u16(self.mode3_length);
+        state.bool(self.dma_active);
+
+        state.bool(self.cgb);
+        state.u8(self.vram_bank);
+        self.bg_color_palettes.save_state(state);
+        self.obj_color_palettes.save_state(state);
+
+        state.u8(self.window_line);
+        for flag in [self.window_drawn, self.window_y_triggered, self.window_wrap, self.last_line,
+                     self.stat_interrupt_line, self.first_line, self.frame_blank, self.skip_frame] {
+            state.bool(flag);
+        }
+
+        state.bytes(&[self.lcd_control, self.lcd_status, self.scy, self.scx, self.ly, self.lyc, self.dma,
+                      self.bgp, self.obp0, self.obp1, self.wy, self.wx]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        state.bytes(&mut self.framebuffer)?;
+        state.bytes(&mut self.vram)?;
+        state.bytes(&mut self.oam)?;
+        self.scanline_counter = state.u16()?;
+        self.frames = state.u64()?;
+        self.fifo.load_state(state)?;
+        self.mode3_length = state.u16()?;
+        self.dma_active = state.bool()?;
+
+        self.cgb = state.bool()?;
+        self.vram_bank = state.u8()? & 1;
+        self.bg_color_palettes.load_state(state)?;
+        self.obj_color_palettes.load_state(state)?;
+
+        self.window_line = state.u8()?;
+        self.window_drawn = state.bool()?;
+        self.window_y_triggered = state.bool()?;
+        self.window_wrap = state.bool()?;
+        self.last_line = state.bool()?;
+        self.stat_interrupt_line = state.bool()?;
+        self.first_line = state.bool()?;
+        self.frame_blank = state.bool()?;
+        self.skip_frame = state.bool()?;
+
+        let mut registers = [0; 12];
+        state.bytes(&mut registers)?;
+        [self.lcd_control, self.lcd_status, self.scy, self.scx, self.ly, self.lyc, self.dma,
+         self.bgp, self.obp0, self.obp1, self.wy, self.wx] = registers;
+        Ok(())
+    }
+}
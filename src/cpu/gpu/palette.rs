@@ -0,0 +1,101 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// RGB colours shown for the four DMG shades, from lightest to darkest.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palette(pub [(u8, u8, u8); 4]);
+
+impl Palette {
+    pub const GREY: Palette = Palette([(0xFF, 0xFF, 0xFF), (0xCC, 0xCC, 0xCC), (0x77, 0x77, 0x77), (0x00, 0x00, 0x00)]);
+    pub const CLASSIC_GREEN: Palette = Palette([(0x9B, 0xBC, 0x0F), (0x8B, 0xAC, 0x0F), (0x30, 0x62, 0x30), (0x0F, 0x38, 0x0F)]);
+    pub const POCKET: Palette = Palette([(0xC4, 0xCF, 0xA1), (0x8B, 0x95, 0x6D), (0x4D, 0x53, 0x3C), (0x1F, 0x1F, 0x1F)]);
+    pub const LIGHT: Palette = Palette([(0x00, 0xB5, 0x81), (0x00, 0x9A, 0x71), (0x00, 0x69, 0x4A), (0x00, 0x4F, 0x3B)]);
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        match name {
+            "grey" => Some(Palette::GREY),
+            "green" => Some(Palette::CLASSIC_GREEN),
+            "pocket" => Some(Palette::POCKET),
+            "light" => Some(Palette::LIGHT),
+            _ => None
+        }
+    }
+
+    pub fn color(&self, shade: u8) -> (u8, u8, u8) {
+        self.0[shade as usize]
+    }
+
+    // Either a preset name or four RRGGBB hex colours.
+    fn parse(value: &str) -> Option<Palette> {
+        if let Some(palette) = Palette::from_name(value) {
+            return Some(palette);
+        }
+
+        let colors: Vec<&str> = value.split_whitespace().collect();
+        if colors.len() != 4 {
+            return None;
+        }
+
+        let mut palette = [(0, 0, 0); 4];
+        for (color, hex) in palette.iter_mut().zip(colors) {
+            let hex = hex.trim_start_matches('#');
+            if hex.len() != 6 {
+                return None;
+            }
+            let rgb = u32::from_str_radix(hex, 16).ok()?;
+            *color = ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+        }
+        Some(Palette(palette))
+    }
+}
+
+// Palettes used for the background/window and the two object palettes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Palettes {
+    pub bg: Palette,
+    pub obp0: Palette,
+    pub obp1: Palette
+}
+
+impl Palettes {
+
+    pub fn new(palette: Palette) -> Palettes {
+        Palettes { bg: palette, obp0: palette, obp1: palette }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Palettes> {
+        Palettes::parse(&fs::read_to_string(path)?)
+    }
+
+    // One `key = value` per line, where the key is `bg`, `obp0`, `obp1` or
+    // `all`, and the value a preset name or four colours, e.g.
+    //
+    //     ; lines starting with ';' are comments
+    //     all  = green
+    //     obp1 = #FFFFFF #FF8484 #943A3A #000000
+    pub fn parse(text: &str) -> io::Result<Palettes> {
+        let mut palettes = Palettes::new(Palette::GREY);
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("Invalid palette on line {}: {}", number + 1, line));
+
+            let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+            let palette = Palette::parse(value.trim()).ok_or_else(invalid)?;
+            match key.trim() {
+                "bg" => palettes.bg = palette,
+                "obp0" => palettes.obp0 = palette,
+                "obp1" => palettes.obp1 = palette,
+                "all" => palettes = Palettes::new(palette),
+                _ => return Err(invalid())
+            }
+        }
+
+        Ok(palettes)
+    }
+}
@@ -0,0 +1,99 @@
+use std::io;
+
+use super::state::{SaveState, StateReader, StateWriter};
+
+// CGB VRAM DMA, copying blocks of 16 bytes to VRAM either all at once
+// (general purpose DMA) or one block per HBlank (HBlank DMA).
+#[derive(Clone)]
+pub struct Hdma {
+    source: u16,
+    // Offset in VRAM
+    destination: u16,
+    // Blocks left minus one, as read from HDMA5
+    remaining: u8,
+    hblank_active: bool
+}
+
+impl Hdma {
+
+    pub fn new() -> Hdma {
+        Hdma { source: 0, destination: 0, remaining: 0x7F, hblank_active: false }
+    }
+
+    // FF51, FF52 - HDMA1, HDMA2: Source high, low
+    pub fn write_source_high(&mut self, data: u8) {
+        self.source = (self.source & 0x00FF) | (data as u16) << 8;
+    }
+
+    pub fn write_source_low(&mut self, data: u8) {
+        self.source = (self.source & 0xFF00) | (data & 0xF0) as u16;
+    }
+
+    // FF53, FF54 - HDMA3, HDMA4: Destination high, low
+    pub fn write_destination_high(&mut self, data: u8) {
+        self.destination = (self.destination & 0x00FF) | ((data & 0x1F) as u16) << 8;
+    }
+
+    pub fn write_destination_low(&mut self, data: u8) {
+        self.destination = (self.destination & 0xFF00) | (data & 0xF0) as u16;
+    }
+
+    // FF55 - HDMA5: Length/mode/start
+    pub fn read_hdma5(&self) -> u8 {
+        (!self.hblank_active as u8) << 7 | self.remaining
+    }
+
+    // Returns the number of blocks to copy right away for a general purpose
+    // DMA, 0 otherwise.
+    pub fn write_hdma5(&mut self, data: u8) -> u8 {
+        if self.hblank_active && (data >> 7) & 1 == 0 {
+            // Cancelled, the remaining length can still be read back
+            self.hblank_active = false;
+            return 0;
+        }
+
+        self.remaining = data & 0x7F;
+        if (data >> 7) & 1 == 1 {
+            self.hblank_active = true;
+            0
+        } else {
+            self.remaining + 1
+        }
+    }
+
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    // Source address and VRAM offset of the next block.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, self.destination);
+        self.source = self.source.wrapping_add(0x10);
+        self.destination = (self.destination + 0x10) & 0x1FF0;
+
+        if self.remaining == 0 {
+            self.hblank_active = false;
+            self.remaining = 0x7F;
+        } else {
+            self.remaining -= 1;
+        }
+        block
+    }
+}
+
+impl SaveState for Hdma {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u16(self.source);
+        state.u16(self.destination);
+        state.u8(self.remaining);
+        state.bool(self.hblank_active);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.source = state.u16()?;
+        self.destination = state.u16()?;
+        self.remaining = state.u8()?;
+        self.hblank_active = state.bool()?;
+        Ok(())
+    }
+}
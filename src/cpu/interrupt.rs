@@ -1,4 +1,7 @@
+use std::io;
+
 use super::register::Register;
+use super::state::{SaveState, StateReader, StateWriter};
 
 #[derive(Clone, Copy)]
 pub enum InterruptHandler {
@@ -9,6 +12,19 @@ pub enum InterruptHandler {
     Joypad
 }
 
+impl InterruptHandler {
+    pub fn vector(&self) -> u16 {
+        match self {
+            InterruptHandler::VBlank => 0x40,
+            InterruptHandler::LCD => 0x48,
+            InterruptHandler::Timer => 0x50,
+            InterruptHandler::Serial => 0x58,
+            InterruptHandler::Joypad => 0x60
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Interrupt {
     interrupt_enable: Register,
     interrupt_flag: Register,
@@ -71,3 +87,16 @@ impl Interrupt {
         self.interrupt_enable.to_u8() & self.interrupt_flag.to_u8() != 0
     }
 }
+
+impl SaveState for Interrupt {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.interrupt_enable.to_u8());
+        state.u8(self.interrupt_flag.to_u8());
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.interrupt_enable.write(state.u8()?);
+        self.interrupt_flag.write(state.u8()?);
+        Ok(())
+    }
+}
@@ -1,7 +1,9 @@
-use core::panic;
+use std::io;
+
 use super::register::Register;
+use super::state::{SaveState, StateReader, StateWriter};
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Button {
     R = 0,
     L = 1,
@@ -13,65 +15,183 @@ pub enum Button {
     STA = 7,
 }
 
-#[derive(Clone, Copy)]
-enum Column { 
-    DPAD,
-    BUTTONS,
-    NONE 
+impl Button {
+    pub const ALL: [Button; 8] = [Button::R, Button::L, Button::U, Button::D, Button::A, Button::B, Button::SEL, Button::STA];
 }
 
+// Frames each turbo press and release lasts by default, 15 presses a second
+pub const DEFAULT_TURBO_RATE: u8 = 2;
+
+const OPPOSING_DIRECTIONS: [(Button, Button); 2] = [(Button::L, Button::R), (Button::U, Button::D)];
+
+#[derive(Clone)]
 pub struct Joypad {
+    // Buttons as the game sees them, 0 when pressed
     buttons: Register,
-    column: Column
+    // P14 and P15 as last written, a 0 selects the d-pad or button row
+    select: u8,
+    // Buttons held by the player and through turbo inputs, one bit per
+    // pressed button at `Button as u8`
+    held: u8,
+    turbo_held: u8,
+    // Frames each turbo press and release lasts, and the frame of the
+    // current press and release
+    turbo_rate: u8,
+    turbo_frame: u8,
+    // Whether Left+Right and Up+Down can be pressed together. When not, both
+    // directions are released.
+    allow_opposing: bool
 }
 
 impl Joypad {
 
     pub fn new() -> Self {
-        Joypad { buttons: Register::new(0), column: Column::NONE }
+        Joypad {
+            buttons: Register::new(0xFF),
+            select: 0,
+            held: 0,
+            turbo_held: 0,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            turbo_frame: 0,
+            allow_opposing: false
+        }
     }
 
-    pub fn read_register(&self) -> u8 {
-        match self.column {
-            Column::DPAD => (0b10 << 4) | self.buttons.low_nibble(),
-            Column::BUTTONS => (0b01 << 4) | self.buttons.high_nibble(),
-            Column::NONE => (0b11 << 4) | 0b1111,
+    // P10-P13: every pressed button in a selected row pulls its line low,
+    // so with both rows selected they read as the AND of the two.
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if (self.select >> 4) & 1 == 0 {
+            lines &= self.buttons.low_nibble();
+        }
+        if (self.select >> 5) & 1 == 0 {
+            lines &= self.buttons.high_nibble();
         }
+        lines
+    }
+
+    // FF00 - P1, the two unused bits read as 1
+    pub fn read_register(&self) -> u8 {
+        0b1100_0000 | self.select | self.lines()
+    }
+
+    // Returns whether a line went low, as selecting a row with a pressed
+    // button also requests the joypad interrupt.
+    pub fn write_register(&mut self, value: u8) -> bool {
+        let lines = self.lines();
+        self.select = value & 0b0011_0000;
+        lines & !self.lines() != 0
     }
 
-    pub fn write_register(&mut self, value: u8) {
-        match value >> 4 & 0b11 {
-            0b01 => self.column = Column::BUTTONS,
-            0b10 => self.column = Column::DPAD,
-            0b11 => self.column = Column::NONE,
-            _ => panic!("Unexpected state")
+    // The buttons the game sees given the held ones, the turbo phase and
+    // the opposing direction filter.
+    fn effective(&self) -> u8 {
+        let mut pressed = self.held;
+        if self.turbo_frame < self.turbo_rate {
+            pressed |= self.turbo_held;
         }
+
+        if !self.allow_opposing {
+            for (first, second) in OPPOSING_DIRECTIONS {
+                let both = 1 << first as u8 | 1 << second as u8;
+                if pressed & both == both {
+                    pressed &= !both;
+                }
+            }
+        }
+        pressed
+    }
+
+    // Applies the held buttons, returning whether one of P10-P13 went from
+    // high to low and the joypad interrupt has to be requested.
+    fn update(&mut self) -> bool {
+        let lines = self.lines();
+        self.buttons.write(!self.effective());
+        lines & !self.lines() != 0
     }
 
     pub fn set_button(&mut self, button: Button) -> bool {
-        
-        let old_value = self.buttons;
-        self.buttons.unset_bit(button as u8);
-
-        let request_joypad = if old_value != self.buttons {
-            let is_dpad = match button {
-                Button::R | Button::L | Button::U | Button::D => true,
-                _ => false
-            };
-
-            match self.column {
-                Column::DPAD => is_dpad,
-                Column::BUTTONS => !is_dpad,
-                Column::NONE => false,
-            }
-        } else {
-            false
-        };
+        self.held |= 1 << button as u8;
+        self.update()
+    }
+
+    pub fn unset_button(&mut self, button: Button) -> bool {
+        self.held &= !(1 << button as u8);
+        self.update()
+    }
+
+    // Sets all the held buttons at once, one bit per pressed button.
+    pub fn set_pressed(&mut self, pressed: u8) -> bool {
+        self.held = pressed;
+        self.update()
+    }
+
+    pub fn set_turbo_pressed(&mut self, pressed: u8) -> bool {
+        if self.turbo_held == 0 && pressed != 0 {
+            self.turbo_frame = 0;
+        }
+        self.turbo_held = pressed;
+        self.update()
+    }
+
+    // Turbo inputs press and release the button every `turbo_rate` frames
+    // for as long as they are held, starting pressed.
+    pub fn set_turbo_button(&mut self, button: Button) -> bool {
+        if self.turbo_held == 0 {
+            self.turbo_frame = 0;
+        }
+        self.turbo_held |= 1 << button as u8;
+        self.update()
+    }
+
+    pub fn unset_turbo_button(&mut self, button: Button) -> bool {
+        self.turbo_held &= !(1 << button as u8);
+        self.update()
+    }
+
+    pub fn set_turbo_rate(&mut self, frames: u8) -> bool {
+        self.turbo_rate = frames.max(1);
+        self.turbo_frame = 0;
+        self.update()
+    }
+
+    pub fn set_allow_opposing(&mut self, allow: bool) -> bool {
+        self.allow_opposing = allow;
+        self.update()
+    }
+
+    // Called at the start of every VBlank to move the turbo phase along.
+    pub fn next_frame(&mut self) -> bool {
+        self.turbo_frame = (self.turbo_frame + 1) % (2 * self.turbo_rate);
+        self.update()
+    }
+
+    // Buttons held by the player, one bit per pressed button at
+    // `Button as u8`.
+    pub fn pressed(&self) -> u8 {
+        self.held
+    }
+
+    pub fn turbo_pressed(&self) -> u8 {
+        self.turbo_held
+    }
+}
 
-        request_joypad
+impl SaveState for Joypad {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.buttons.to_u8());
+        state.u8(self.select);
+        state.u8(self.held);
+        state.u8(self.turbo_held);
+        state.u8(self.turbo_frame);
     }
 
-    pub fn unset_button(&mut self, button: Button) {
-        self.buttons.set_bit(button as u8);
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.buttons.write(state.u8()?);
+        self.select = state.u8()? & 0b0011_0000;
+        self.held = state.u8()?;
+        self.turbo_held = state.u8()?;
+        self.turbo_frame = state.u8()? % (2 * self.turbo_rate);
+        Ok(())
     }
 }
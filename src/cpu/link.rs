@@ -0,0 +1,114 @@
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::thread;
+
+// What is plugged into the link port. Bytes are exchanged whole: the side
+// running on its internal clock starts a transfer with the byte it shifts
+// out, and the other side answers with its own.
+pub trait Link {
+    // Internal clock: sends the byte shifted out by a transfer.
+    fn start_transfer(&mut self, data: u8);
+
+    // Internal clock: the byte the other side answered the transfer with,
+    // None while it has not yet. 0xFF once the cable is unplugged.
+    fn poll_reply(&mut self) -> Option<u8>;
+
+    // External clock: the byte of a transfer started by the other side,
+    // which has to be answered with `reply`.
+    fn poll_transfer(&mut self) -> Option<u8>;
+
+    fn reply(&mut self, data: u8);
+}
+
+const TRANSFER: u8 = 0;
+const REPLY: u8 = 1;
+
+// Link cable to another emulator over TCP. Every message is two bytes: its
+// kind, transfer or reply, and the data.
+pub struct TcpLink {
+    stream: TcpStream,
+    received: Vec<u8>,
+    transfer: Option<u8>,
+    reply: Option<u8>,
+    connected: bool
+}
+
+impl TcpLink {
+
+    pub fn new(stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        stream.set_nonblocking(true)?;
+        Ok(TcpLink { stream, received: Vec::new(), transfer: None, reply: None, connected: true })
+    }
+
+    // Waits for the other emulator to connect.
+    pub fn listen(address: impl ToSocketAddrs) -> io::Result<TcpLink> {
+        let (stream, _) = TcpListener::bind(address)?.accept()?;
+        TcpLink::new(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<TcpLink> {
+        TcpLink::new(TcpStream::connect(address)?)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    fn send(&mut self, kind: u8, data: u8) {
+        let mut message: &[u8] = &[kind, data];
+        while self.connected && !message.is_empty() {
+            match self.stream.write(message) {
+                Ok(0) => self.connected = false,
+                Ok(written) => message = &message[written..],
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::yield_now(),
+                Err(_) => self.connected = false
+            }
+        }
+    }
+
+    // Reads whatever arrived without waiting.
+    fn receive(&mut self) {
+        let mut buffer = [0; 64];
+        while self.connected {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => self.connected = false,
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => self.connected = false
+            }
+        }
+
+        let messages = self.received.len() / 2 * 2;
+        for message in self.received[..messages].chunks_exact(2) {
+            match message[0] {
+                TRANSFER => self.transfer = Some(message[1]),
+                _ => self.reply = Some(message[1])
+            }
+        }
+        self.received.drain(..messages);
+    }
+}
+
+impl Link for TcpLink {
+    fn start_transfer(&mut self, data: u8) {
+        // A late answer to an earlier transfer is not for this one
+        self.receive();
+        self.reply = None;
+        self.send(TRANSFER, data);
+    }
+
+    fn poll_reply(&mut self) -> Option<u8> {
+        self.receive();
+        self.reply.take().or((!self.connected).then_some(0xFF))
+    }
+
+    fn poll_transfer(&mut self) -> Option<u8> {
+        self.receive();
+        self.transfer.take()
+    }
+
+    fn reply(&mut self, data: u8) {
+        self.send(REPLY, data);
+    }
+}
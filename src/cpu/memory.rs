@@ -1,10 +1,21 @@
+use std::cell::RefCell;
 use std::fs;
+use std::io;
+use std::rc::Rc;
 
+use super::bus::Bus;
 use super::timer::Timer;
 use super::interrupt::{Interrupt, InterruptHandler};
-use super::gpu::GPU;
+use super::gpu::{GPU, Palette, Palettes, Renderer};
 use super::joypad::{Joypad, Button};
-
+use super::link::Link;
+use super::serial::Serial;
+use super::dma::OamDma;
+use super::hdma::Hdma;
+use super::sgb::Sgb;
+use super::state::{SaveState, StateReader, StateWriter};
+
+#[derive(Clone)]
 struct Bootrom {
     code: [u8; 0x100],
     enabled: bool
@@ -40,13 +51,15 @@ impl Bootrom {
     }
 }
 
+#[derive(Clone)]
 pub struct Memory {
 
     bootrom: Bootrom,
     rom_bank_0: [u8; 0x4000],
     rom_bank_n: [u8; 0x4000],
     external_ram: [u8; 0x2000],
-    work_ram: [u8; 0x2000],
+    // 8 banks of 4 KiB, banks 2-7 are only reachable in CGB mode
+    work_ram: [u8; 0x8000],
 
     // io_registers: [u8; 0x80],
     high_ram: [u8; 0x7F],
@@ -54,7 +67,24 @@ pub struct Memory {
     timer: Timer,
     interrupt: Interrupt,
     gpu: GPU,
-    joypad: Joypad
+    joypad: Joypad,
+    serial: Serial,
+    dma: OamDma,
+    hdma: Hdma,
+    // M-cycles the CPU is halted for by VRAM DMA
+    stall_cycles: u16,
+
+    cgb: bool,
+    // FF70 - SVBK: WRAM bank mapped at 0xD000
+    wram_bank: u8,
+    // FF4D - KEY1: speed switch
+    double_speed: bool,
+    prepare_speed_switch: bool,
+    // Run DMG games as on a CGB, with the boot ROM compatibility palettes
+    cgb_compatibility: bool,
+    // Run SGB enhanced games as on a Super Game Boy
+    sgb_enabled: bool,
+    sgb: Option<Sgb>
 }
 
 impl Memory {
@@ -66,14 +96,25 @@ impl Memory {
             rom_bank_0: [0; 0x4000],
             rom_bank_n: [0; 0x4000],
             external_ram: [0; 0x2000],
-            work_ram: [0; 0x2000],
+            work_ram: [0; 0x8000],
             // io_registers: [0; 0x80],
             high_ram: [0; 0x7F],
 
             timer: Timer::new(),
             interrupt: Interrupt::new(),
             gpu: GPU::new(),
-            joypad: Joypad::new()
+            joypad: Joypad::new(),
+            serial: Serial::new(),
+            dma: OamDma::new(),
+            hdma: Hdma::new(),
+            stall_cycles: 0,
+            cgb: false,
+            wram_bank: 1,
+            double_speed: false,
+            prepare_speed_switch: false,
+            cgb_compatibility: false,
+            sgb_enabled: false,
+            sgb: None
         }
     }
 
@@ -82,6 +123,60 @@ impl Memory {
         let (bank_0, bank_1) = data.split_at(0x4000);
         self.rom_bank_0.copy_from_slice(bank_0);
         self.rom_bank_n.copy_from_slice(bank_1);
+
+        // CGB flag at 0x0143: 0x80 for CGB enhanced, 0xC0 for CGB only
+        if (self.rom_bank_0[0x143] >> 7) & 1 == 1 {
+            self.start_cgb_mode();
+        } else if self.cgb_compatibility {
+            self.gpu.use_compatibility_palettes(&self.rom_bank_0);
+        } else if self.sgb_enabled && self.rom_bank_0[0x146] == 0x03 && self.rom_bank_0[0x14B] == 0x33 {
+            // SGB flag at 0x0146, only honoured with the new licensee code
+            self.start_sgb_mode();
+        }
+    }
+
+    // The SGB colours the DMG shades itself, so the frame is drawn with known
+    // greys it can map back to shades.
+    fn start_sgb_mode(&mut self) {
+        self.sgb = Some(Sgb::new());
+        self.gpu.set_palettes(Palettes::new(Palette::GREY));
+    }
+
+    // Only the DMG boot ROM is available, so CGB games start straight from
+    // the state the CGB boot ROM leaves the hardware in.
+    fn start_cgb_mode(&mut self) {
+        self.cgb = true;
+        self.gpu.set_cgb_mode(true);
+        self.serial.set_cgb_mode(true);
+        self.bootrom.set_disable();
+        self.gpu.write_lcd_control(0x91);
+        self.gpu.write_bgp(0xFC);
+    }
+
+    // Must be set before loading the ROM.
+    pub fn set_sgb(&mut self, enabled: bool) {
+        self.sgb_enabled = enabled;
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        self.sgb.is_some()
+    }
+
+    // Must be set before loading the ROM.
+    pub fn set_cgb_compatibility(&mut self, enabled: bool) {
+        self.cgb_compatibility = enabled;
+    }
+
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.gpu.set_color_correction(enabled);
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.cgb
+    }
+
+    pub fn is_double_speed(&self) -> bool {
+        self.double_speed
     }
 
     fn read_rom_bank_0(&self, address: u16) -> u8 {
@@ -100,8 +195,15 @@ impl Memory {
         self.external_ram[address as usize]
     }
     
+    fn work_ram_index(&self, address: u16) -> usize {
+        match address {
+            0x0000..=0x0FFF => address as usize,
+            _ => self.wram_bank as usize * 0x1000 + (address as usize - 0x1000)
+        }
+    }
+
     fn read_work_ram(&self, address: u16) -> u8 {
-        self.work_ram[address as usize]
+        self.work_ram[self.work_ram_index(address)]
     }
 
     // fn read_io_registers(&self, address: u16) -> u8 {
@@ -117,7 +219,8 @@ impl Memory {
     }
 
     fn write_work_ram(&mut self, address: u16, data: u8) {
-        self.work_ram[address as usize] = data;
+        let index = self.work_ram_index(address);
+        self.work_ram[index] = data;
     }
 
     fn write_video_ram(&mut self, address: u16, data: u8) {
@@ -132,44 +235,6 @@ impl Memory {
         self.high_ram[address as usize] = data;
     }
     
-    pub fn read(&self, address: u16) -> u8 {
-        match address {
-            0x0000..=0x00FF => {
-                if self.bootrom.is_enabled() {
-                    self.bootrom.read(address)
-                } else {
-                    self.read_rom_bank_0(address)
-                }
-            }
-            0x0000..=0x3FFF => self.read_rom_bank_0(address),
-            0x4000..=0x7FFF => self.read_rom_bank_n(address - 0x4000),
-            0x8000..=0x9FFF => self.read_video_ram(address - 0x8000),
-            0xA000..=0xBFFF => self.read_external_ram(address - 0xA000),
-            0xC000..=0xDFFF => self.read_work_ram(address - 0xC000),
-            0xE000..=0xFDFF => self.read_work_ram(address - 0xE000),
-            0xFF00..=0xFF7F => self.handle_read_io_register(address),
-            0xFF80..=0xFFFE => self.read_high_ram(address - 0xFF80),
-            0xFFFF => self.interrupt.read_interrupt_enable(),
-            x => panic!("Accessed reading unimplemented area: {:x}", x)
-        }
-    }
-
-    pub fn write(&mut self, address: u16, data: u8) {
-        match address {
-            0x0000..=0x7FFF => {},
-            0x8000..=0x9FFF => self.write_video_ram(address - 0x8000, data),
-            0xA000..=0xBFFF => self.write_external_ram(address - 0xA000, data),
-            0xC000..=0xDFFF => self.write_work_ram(address - 0xC000, data),
-            0xFE00..=0xFE9F => self.gpu.write_oam(address - 0xFE00, data),
-            0xFEA0..=0xFEFF => {},
-            0xFF00..=0xFF7F => self.handle_write_io_register(address, data),
-            0xFF80..=0xFFFE => self.write_high_ram(address - 0xFF80, data),
-            0xFFFF => self.interrupt.write_interrupt_enable(data),
-            x => panic!("Accessed writing unimplemented area: {:x}", x)
-        }
-    }
-
-
     fn handle_read_io_register(&self, address: u16) -> u8 {
         
         // println!("[IO REA] {:#06x} = {}", address, res);
@@ -177,14 +242,32 @@ impl Memory {
             0xFF00 => {
                 let res = self.joypad.read_register();
                 // println!("{:#010b}", res);
-                res
+                match &self.sgb {
+                    Some(sgb) => sgb.read_joypad(res),
+                    None => res
+                }
             },
+            0xFF01 => self.serial.read_sb(),
+            0xFF02 => self.serial.read_sc(),
             0xFF04 => self.timer.read_div(),
             0xFF0F => self.interrupt.read_interrupt_flag(),
             0xFF40 => self.gpu.read_lcd_control(),
             0xFF41 => self.gpu.read_lcd_status(),
             0xFF42 => self.gpu.read_scy(),
             0xFF44 => self.gpu.read_ly(),
+            0xFF45 => self.gpu.read_lyc(),
+            0xFF46 => self.gpu.read_dma(),
+            // CGB registers read as 0xFF on DMG
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.cgb => 0xFF,
+            0xFF4D => 0x7E | (self.double_speed as u8) << 7 | self.prepare_speed_switch as u8,
+            0xFF4F => self.gpu.read_vbk(),
+            0xFF51..=0xFF54 => 0xFF,
+            0xFF55 => self.hdma.read_hdma5(),
+            0xFF68 => self.gpu.read_bcps(),
+            0xFF69 => self.gpu.read_bcpd(),
+            0xFF6A => self.gpu.read_ocps(),
+            0xFF6B => self.gpu.read_ocpd(),
+            0xFF70 => 0xF8 | self.wram_bank,
             x => panic!("Reading unknown IO Register {:x}", x)
         }
     }
@@ -193,12 +276,14 @@ impl Memory {
         // println!("[IO WRI] {:#06x} = {:#04x}", address, data);
         match address {
             0xFF00 => {
-                self.joypad.write_register(data);
-            },
-            0xFF01 => {
-                // print!("{}", data as char)
+                let request = self.joypad.write_register(data);
+                self.request_joypad_interrupt(request);
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.write_joypad(data);
+                }
             },
-            0xFF02 => {},
+            0xFF01 => self.serial.write_sb(data),
+            0xFF02 => self.serial.write_sc(data),
             0xFF04 => self.timer.reset_div(),
             0xFF05 => self.timer.write_tima(data),
             0xFF06 => self.timer.write_tma(data),
@@ -207,55 +292,211 @@ impl Memory {
             0xFF10..=0xFF26 => {}, // Audio
             0xFF30..=0xFF3F => {}, // Wave RAM
             0xFF40 => self.gpu.write_lcd_control(data),
-            0xFF41 => self.gpu.write_lcd_status(data),
+            0xFF41 => {
+                if self.gpu.write_lcd_status(data) {
+                    self.interrupt.set_if_bit(InterruptHandler::LCD);
+                }
+            },
             0xFF42 => self.gpu.write_scy(data),
             0xFF43 => self.gpu.write_scx(data),
+            0xFF45 => {
+                if self.gpu.write_lyc(data) {
+                    self.interrupt.set_if_bit(InterruptHandler::LCD);
+                }
+            },
             0xFF46 => {
-                let source: u16 = (data as u16) << 8;
-                let data: Vec<u8> = (0..0xA0).map(|i| {self.read(source + i)}).collect();
-                self.gpu.oam_dma(&data);
+                self.gpu.write_dma(data);
+                self.dma.start(data);
             },
             0xFF47 => self.gpu.write_bgp(data),
             0xFF48 => self.gpu.write_obp0(data),
             0xFF49 => self.gpu.write_obp1(data),
             0xFF4A => self.gpu.write_wy(data),
             0xFF4B => self.gpu.write_wx(data),
+            0xFF4D | 0xFF4F | 0xFF51..=0xFF55 | 0xFF68..=0xFF6B | 0xFF70 if !self.cgb => {},
+            0xFF4D => self.prepare_speed_switch = data & 1 == 1,
+            0xFF4F => self.gpu.write_vbk(data),
+            0xFF51 => self.hdma.write_source_high(data),
+            0xFF52 => self.hdma.write_source_low(data),
+            0xFF53 => self.hdma.write_destination_high(data),
+            0xFF54 => self.hdma.write_destination_low(data),
+            0xFF55 => {
+                let blocks = self.hdma.write_hdma5(data);
+                for _ in 0..blocks {
+                    self.copy_hdma_block();
+                }
+            },
             0xFF50 => {
                 println!("Disabled bootrom!");
                 self.bootrom.set_disable();
             },
+            0xFF68 => self.gpu.write_bcps(data),
+            0xFF69 => self.gpu.write_bcpd(data),
+            0xFF6A => self.gpu.write_ocps(data),
+            0xFF6B => self.gpu.write_ocpd(data),
+            // Bank 0 selects bank 1
+            0xFF70 => self.wram_bank = (data & 0b111).max(1),
             0xFF7F => {},
             x => panic!("Writing unknown IO Register {:x}", x)
         }
     }
 
-    pub fn update_timer(&mut self, cycles: u8) {
+    fn update_timer(&mut self, cycles: u8) {
         let interrupt = self.timer.update(cycles);
         if interrupt {
             self.get_interrupts().set_if_bit(InterruptHandler::Timer);
         }
     }
 
-    pub fn update_gpu(&mut self, cycles: u8) {
-        let (vblank, lcd) = self.gpu.update(cycles);
+    fn update_serial(&mut self, cycles: u8) {
+        let interrupt = self.serial.update(cycles);
+        if interrupt {
+            self.get_interrupts().set_if_bit(InterruptHandler::Serial);
+        }
+    }
+
+    fn update_dma(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if let Some((source, index)) = self.dma.step() {
+                let value = self.read_mapped(source);
+                self.dma.set_value(value);
+                self.gpu.write_oam_dma(index, value);
+            }
+        }
+        self.gpu.set_dma_active(self.dma.is_active());
+    }
+
+    // Copies 16 bytes to VRAM, halting the CPU for 8 M-cycles at normal speed.
+    fn copy_hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for i in 0..0x10 {
+            let value = self.read_mapped(source.wrapping_add(i));
+            self.gpu.write_vram_dma(destination + i, value);
+        }
+        self.stall_cycles += if self.double_speed { 16 } else { 8 };
+    }
+
+    // While OAM DMA runs the CPU only has access to the IO registers and HRAM.
+    // OAM reads 0xFF, and the bus used by the transfer returns the byte being
+    // copied.
+    fn dma_conflict(&self, address: u16) -> Option<u8> {
+        let video_bus = |address: u16| (0x8000..=0x9FFF).contains(&address);
+
+        if !self.dma.is_active() {
+            return None;
+        }
+        match address {
+            0xFF00..=0xFFFF => None,
+            0xFE00..=0xFEFF => Some(0xFF),
+            _ if video_bus(address) == video_bus(self.dma.source()) => Some(self.dma.value()),
+            _ => None
+        }
+    }
+
+    fn read_mapped(&mut self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x00FF => {
+                if self.bootrom.is_enabled() {
+                    self.bootrom.read(address)
+                } else {
+                    self.read_rom_bank_0(address)
+                }
+            }
+            0x0100..=0x3FFF => self.read_rom_bank_0(address),
+            0x4000..=0x7FFF => self.read_rom_bank_n(address - 0x4000),
+            0x8000..=0x9FFF => self.read_video_ram(address - 0x8000),
+            0xA000..=0xBFFF => self.read_external_ram(address - 0xA000),
+            0xC000..=0xDFFF => self.read_work_ram(address - 0xC000),
+            0xE000..=0xFDFF => self.read_work_ram(address - 0xE000),
+            0xFE00..=0xFE9F => self.gpu.read_oam(address - 0xFE00),
+            0xFEA0..=0xFEFF => if self.gpu.oam_blocked() { 0xFF } else { 0x00 },
+            0xFF00..=0xFF7F => self.handle_read_io_register(address),
+            0xFF80..=0xFFFE => self.read_high_ram(address - 0xFF80),
+            0xFFFF => self.interrupt.read_interrupt_enable(),
+        }
+    }
+
+    fn update_gpu(&mut self, cycles: u8) {
+        let dots = cycles as u16 * self.dots_per_cycle() as u16;
+        let was_drawing = self.gpu.read_lcd_status() & 0b11 == 0b11;
+        let (vblank, lcd) = self.gpu.update(dots);
+
+        // HBlank DMA copies a block at the start of each HBlank
+        if was_drawing && self.gpu.read_lcd_status() & 0b11 == 0 && self.hdma.is_hblank_active() {
+            self.copy_hdma_block();
+        }
         if vblank {
             // println!("SET VBLANK INTERRUPT FLAG");
             self.get_interrupts().set_if_bit(InterruptHandler::VBlank);
+
+            let request = self.joypad.next_frame();
+            self.request_joypad_interrupt(request);
+
+            if let Some(sgb) = &mut self.sgb {
+                // VRAM transfers read the screen of the frame after the command
+                if sgb.has_pending_transfer() {
+                    sgb.transfer(&self.gpu.screen_tile_data());
+                }
+                sgb.colorize(&self.gpu.framebuffer);
+            }
         }
         if lcd {
             self.get_interrupts().set_if_bit(InterruptHandler::LCD);
         }
     }
 
-    pub fn set_button(&mut self, button: Button) {
-        let request_joystick = self.joypad.set_button(button);
-        if request_joystick {
+    fn request_joypad_interrupt(&mut self, request: bool) {
+        if request {
             self.get_interrupts().set_if_bit(InterruptHandler::Joypad);
         }
     }
 
+    pub fn set_button(&mut self, button: Button) {
+        let request = self.joypad.set_button(button);
+        self.request_joypad_interrupt(request);
+    }
+
     pub fn unset_button(&mut self, button: Button) {
-        self.joypad.unset_button(button);
+        let request = self.joypad.unset_button(button);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn set_turbo_button(&mut self, button: Button) {
+        let request = self.joypad.set_turbo_button(button);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn unset_turbo_button(&mut self, button: Button) {
+        let request = self.joypad.unset_turbo_button(button);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn set_turbo_rate(&mut self, frames: u8) {
+        let request = self.joypad.set_turbo_rate(frames);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn set_allow_opposing(&mut self, allow: bool) {
+        let request = self.joypad.set_allow_opposing(allow);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn pressed_buttons(&self) -> u8 {
+        self.joypad.pressed()
+    }
+
+    pub fn set_pressed_buttons(&mut self, pressed: u8) {
+        let request = self.joypad.set_pressed(pressed);
+        self.request_joypad_interrupt(request);
+    }
+
+    pub fn turbo_buttons(&self) -> u8 {
+        self.joypad.turbo_pressed()
+    }
+
+    pub fn set_turbo_buttons(&mut self, pressed: u8) {
+        let request = self.joypad.set_turbo_pressed(pressed);
+        self.request_joypad_interrupt(request);
     }
 
     pub fn get_interrupts(&mut self) -> &mut Interrupt {
@@ -266,5 +507,163 @@ impl Memory {
         &self.gpu
     }
 
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.gpu.set_renderer(renderer);
+    }
+
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        if self.sgb.is_none() {
+            self.gpu.set_palettes(palettes);
+        }
+    }
+
+    pub fn framebuffer(&self) -> &[u8; 160*144*4] {
+        match &self.sgb {
+            Some(sgb) => sgb.screen(),
+            None => &self.gpu.framebuffer
+        }
+    }
+
+    pub fn sgb_border_frame(&self) -> Option<Vec<u8>> {
+        self.sgb.as_ref().map(|sgb| sgb.render_border())
+    }
+
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.gpu.set_access_blocking(enabled);
+    }
+
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.gpu.set_rendering(enabled);
+    }
+
+    // Header checksum and global checksum of the cartridge, which tell save
+    // states of different ROMs apart.
+    pub fn rom_checksums(&self) -> (u8, u16) {
+        (self.rom_bank_0[0x14D], u16::from_be_bytes([self.rom_bank_0[0x14E], self.rom_bank_0[0x14F]]))
+    }
+
+    pub fn set_link(&mut self, link: Option<Rc<RefCell<dyn Link>>>) {
+        self.serial.set_link(link);
+    }
+
+    pub fn set_serial_capture(&mut self, enabled: bool) {
+        self.serial.set_capture(enabled);
+    }
+
+    pub fn serial_output(&self) -> &[u8] {
+        self.serial.output()
+    }
+}
+
+impl Bus for Memory {
+
+    fn read(&mut self, address: u16) -> u8 {
+        match self.dma_conflict(address) {
+            Some(value) => value,
+            None => self.read_mapped(address)
+        }
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        if self.dma_conflict(address).is_some() {
+            return;
+        }
+        match address {
+            0x0000..=0x7FFF => {},
+            0x8000..=0x9FFF => self.write_video_ram(address - 0x8000, data),
+            0xA000..=0xBFFF => self.write_external_ram(address - 0xA000, data),
+            0xC000..=0xDFFF => self.write_work_ram(address - 0xC000, data),
+            0xFE00..=0xFE9F => self.gpu.write_oam(address - 0xFE00, data),
+            0xFEA0..=0xFEFF => {},
+            0xFF00..=0xFF7F => self.handle_write_io_register(address, data),
+            0xFF80..=0xFFFE => self.write_high_ram(address - 0xFF80, data),
+            0xFFFF => self.interrupt.write_interrupt_enable(data),
+            x => panic!("Accessed writing unimplemented area: {:x}", x)
+        }
+    }
+
+    fn stop(&mut self) -> bool {
+        if self.cgb && self.prepare_speed_switch {
+            self.double_speed = !self.double_speed;
+            self.prepare_speed_switch = false;
+            return true;
+        }
+        false
+    }
+
+    fn take_stall_cycles(&mut self) -> u16 {
+        std::mem::take(&mut self.stall_cycles)
+    }
 
+    // The PPU keeps its speed in double speed mode
+    fn dots_per_cycle(&self) -> u32 {
+        if self.double_speed { 2 } else { 4 }
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.update_timer(cycles);
+        self.update_serial(cycles);
+        self.update_dma(cycles);
+        self.update_gpu(cycles);
+    }
+}
+
+// The ROM and the emulator options are not saved: a state can only be loaded
+// into the same game.
+impl SaveState for Memory {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.bootrom.is_enabled());
+        state.bytes(&self.external_ram);
+        state.bytes(&self.work_ram);
+        state.bytes(&self.high_ram);
+
+        self.timer.save_state(state);
+        self.interrupt.save_state(state);
+        self.gpu.save_state(state);
+        self.joypad.save_state(state);
+        self.serial.save_state(state);
+        self.dma.save_state(state);
+        self.hdma.save_state(state);
+        state.u16(self.stall_cycles);
+
+        state.bool(self.cgb);
+        state.u8(self.wram_bank);
+        state.bool(self.double_speed);
+        state.bool(self.prepare_speed_switch);
+
+        state.bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.bootrom.enabled = state.bool()?;
+        state.bytes(&mut self.external_ram)?;
+        state.bytes(&mut self.work_ram)?;
+        state.bytes(&mut self.high_ram)?;
+
+        self.timer.load_state(state)?;
+        self.interrupt.load_state(state)?;
+        self.gpu.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.dma.load_state(state)?;
+        self.hdma.load_state(state)?;
+        self.stall_cycles = state.u16()?;
+
+        self.cgb = state.bool()?;
+        self.wram_bank = (state.u8()? & 7).max(1);
+        self.double_speed = state.bool()?;
+        self.prepare_speed_switch = state.bool()?;
+
+        self.sgb = None;
+        if state.bool()? {
+            self.start_sgb_mode();
+            if let Some(sgb) = &mut self.sgb {
+                sgb.load_state(state)?;
+            }
+        }
+        Ok(())
+    }
 }
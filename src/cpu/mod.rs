@@ -1,91 +1,400 @@
-mod registers;
 mod memory;
 mod timer;
-mod interrupt;
 mod gpu;
 mod register;
+mod serial;
+mod dma;
+mod hdma;
+mod sgb;
+mod state;
 
-pub(crate) mod joypad;
+pub(crate) mod registers;
+
+pub mod bus;
+pub mod interrupt;
+pub mod joypad;
+pub mod link;
+
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::rc::Rc;
 
 use num_traits::FromPrimitive;
 use registers::Registers;
 use registers::Flag;
-use memory::Memory;
+pub use memory::Memory;
+pub use gpu::{Palette, Palettes, Renderer};
+pub use sgb::{BORDER_WIDTH, BORDER_HEIGHT};
 
 use crate::cpu::registers::DoubleRegister;
+use crate::cpu::registers::DoubleRegisterStack;
 use crate::cpu::registers::Register;
 
+use self::bus::Bus;
 use self::interrupt::InterruptHandler;
 use self::joypad::Button;
-use self::joypad::Joypad;
+use self::link::Link;
+use self::state::{invalid_data, SaveState, StateReader, StateWriter};
 
-pub struct CPU {
+// A frame lasts 154 lines of 456 dots, about 59.73 frames per second.
+pub const DOTS_PER_LINE: u32 = 456;
+pub const DOTS_PER_FRAME: u32 = 154 * DOTS_PER_LINE;
+pub const FRAME_RATE: f64 = 4194304.0 / DOTS_PER_FRAME as f64;
+
+pub struct CPU<B: Bus = Memory> {
 
     registers: Registers,
-    memory: Memory,
+    bus: B,
     enable_interrupts: bool,
     ime: bool,
-    halted: bool
+    halted: bool,
+    breakpoint: bool,
+    // Dots run past the end of the last `run_frame` or `run_scanline`, taken
+    // off the next one
+    extra_dots: u32,
+    // Last frame reported by `take_new_frame`
+    presented_frame: u64
 }
 
-impl CPU {
+impl CPU<Memory> {
 
     pub fn new() -> CPU {
+        CPU::with_bus(Memory::new())
+    }
+
+    pub fn set_button(&mut self, button: Button) {
+        self.bus.set_button(button);
+    }
+
+    pub fn unset_button(&mut self, button: Button) {
+        self.bus.unset_button(button);
+    }
+
+    // Turbo inputs keep pressing and releasing their button every
+    // `set_turbo_rate` frames while held.
+    pub fn set_turbo_button(&mut self, button: Button) {
+        self.bus.set_turbo_button(button);
+    }
+
+    pub fn unset_turbo_button(&mut self, button: Button) {
+        self.bus.unset_turbo_button(button);
+    }
+
+    // Frames each turbo press and release lasts, `DEFAULT_TURBO_RATE` unless
+    // set.
+    pub fn set_turbo_rate(&mut self, frames: u8) {
+        self.bus.set_turbo_rate(frames);
+    }
+
+    // Lets Left+Right and Up+Down be pressed together, which some games break
+    // on. By default both directions are released instead.
+    pub fn set_allow_opposing(&mut self, allow: bool) {
+        self.bus.set_allow_opposing(allow);
+    }
+
+    // Buttons held, one bit per pressed button at `Button as u8`.
+    pub fn pressed_buttons(&self) -> u8 {
+        self.bus.pressed_buttons()
+    }
+
+    pub fn set_pressed_buttons(&mut self, pressed: u8) {
+        self.bus.set_pressed_buttons(pressed);
+    }
+
+    // Turbo inputs held, like `pressed_buttons`.
+    pub fn turbo_buttons(&self) -> u8 {
+        self.bus.turbo_buttons()
+    }
+
+    pub fn set_turbo_buttons(&mut self, pressed: u8) {
+        self.bus.set_turbo_buttons(pressed);
+    }
+
+    pub fn get_framebuffer(&self) -> [u8; 160*144*4] {
+        *self.bus.framebuffer()
+    }
+
+    // The SGB border with the game screen in the middle, as a
+    // `BORDER_WIDTH`x`BORDER_HEIGHT` RGBA image. None unless running in SGB
+    // mode.
+    pub fn get_sgb_border_frame(&self) -> Option<Vec<u8>> {
+        self.bus.sgb_border_frame()
+    }
+
+    pub fn is_frame_blank(&self) -> bool {
+        self.bus.get_gpu().is_frame_blank()
+    }
+
+    pub fn blank_color(&self) -> (u8, u8, u8) {
+        self.bus.get_gpu().blank_color()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.bus.get_gpu().frame_count()
+    }
+
+    // True once for every frame completed since the last call, so frontends
+    // only present whole frames.
+    pub fn take_new_frame(&mut self) -> bool {
+        let frame = self.frame_count();
+        std::mem::replace(&mut self.presented_frame, frame) != frame
+    }
+
+    // Runs until the PPU enters VBlank, leaving a complete frame in the
+    // framebuffer. While the LCD is off it gives up after a frame's worth of
+    // dots and returns false.
+    pub fn run_until_vblank(&mut self) -> bool {
+        let frame = self.frame_count();
+        let mut dots = 0;
+        while dots < DOTS_PER_FRAME {
+            dots += self.step_instruction() * self.bus.dots_per_cycle();
+            if self.frame_count() != frame {
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn load_rom(&mut self, data: Vec<u8>) {
+        self.bus.load_rom(data);
+
+        if self.bus.is_cgb() {
+            // Registers as left by the CGB boot ROM
+            self.registers.write_double_register_stack(&DoubleRegisterStack::AF, 0x1180);
+            self.registers.write_double_register(&DoubleRegister::BC, 0x0000);
+            self.registers.write_double_register(&DoubleRegister::DE, 0xFF56);
+            self.registers.write_double_register(&DoubleRegister::HL, 0x000D);
+            self.registers.write_double_register(&DoubleRegister::SP, 0xFFFE);
+            self.registers.write_pc(0x0100);
+        }
+    }
+
+    pub fn is_cgb(&self) -> bool {
+        self.bus.is_cgb()
+    }
+
+    // Runs SGB enhanced games as a Super Game Boy would, colouring the screen
+    // and drawing a border with the packets the game sends. Must be set before
+    // `load_rom`.
+    pub fn set_sgb(&mut self, enabled: bool) {
+        self.bus.set_sgb(enabled);
+    }
+
+    pub fn is_sgb(&self) -> bool {
+        self.bus.is_sgb()
+    }
+
+    // Runs DMG games as a CGB would, with the colour palettes its boot ROM
+    // picks from the cartridge title. Must be set before `load_rom`.
+    pub fn set_cgb_compatibility(&mut self, enabled: bool) {
+        self.bus.set_cgb_compatibility(enabled);
+    }
+
+    // Mimics the colours of the CGB screen instead of showing the raw RGB555
+    // values.
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.bus.set_color_correction(enabled);
+    }
+
+    pub fn set_renderer(&mut self, renderer: Renderer) {
+        self.bus.set_renderer(renderer);
+    }
+
+    pub fn set_palettes(&mut self, palettes: Palettes) {
+        self.bus.set_palettes(palettes);
+    }
+
+    // Skips drawing the frames that will not be shown, e.g. while
+    // fast-forwarding. The emulation itself is unaffected.
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.bus.set_rendering(enabled);
+    }
+
+    // Snapshot of the whole machine, which `load_state` can restore as long as
+    // the same ROM is loaded.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(state::MAGIC);
+        state.u16(state::VERSION);
+        let (header_checksum, global_checksum) = self.bus.rom_checksums();
+        state.u8(header_checksum);
+        state.u16(global_checksum);
+
+        self.registers.save_state(&mut state);
+        state.bool(self.enable_interrupts);
+        state.bool(self.ime);
+        state.bool(self.halted);
+        state.u32(self.extra_dots);
+        self.bus.save_state(&mut state);
+        state.into_bytes()
+    }
+
+    // Leaves the machine untouched if the state is invalid or was saved with
+    // another ROM.
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut state = StateReader::new(data);
+        let mut magic = [0; 8];
+        state.bytes(&mut magic)?;
+        if magic != *state::MAGIC {
+            return Err(invalid_data("Not a save state"));
+        }
+        let version = state.u16()?;
+        if version != state::VERSION {
+            return Err(invalid_data(&format!("Unsupported save state version {}", version)));
+        }
+        if (state.u8()?, state.u16()?) != self.bus.rom_checksums() {
+            return Err(invalid_data("Save state belongs to a different ROM"));
+        }
+
+        let mut registers = self.registers.clone();
+        registers.load_state(&mut state)?;
+        let enable_interrupts = state.bool()?;
+        let ime = state.bool()?;
+        let halted = state.bool()?;
+        let extra_dots = state.u32()?;
+        let mut bus = self.bus.clone();
+        bus.load_state(&mut state)?;
+        if !state.is_empty() {
+            return Err(invalid_data("Unexpected data after the save state"));
+        }
+
+        self.registers = registers;
+        self.enable_interrupts = enable_interrupts;
+        self.ime = ime;
+        self.halted = halted;
+        self.extra_dots = extra_dots;
+        self.bus = bus;
+        Ok(())
+    }
+
+    pub fn save_state_file(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.save_state())
+    }
+
+    pub fn load_state_file(&mut self, path: &Path) -> io::Result<()> {
+        self.load_state(&fs::read(path)?)
+    }
+
+    // Lets the CPU access VRAM and OAM regardless of the PPU mode, which
+    // helps when debugging games that race the PPU.
+    pub fn set_access_blocking(&mut self, enabled: bool) {
+        self.bus.set_access_blocking(enabled);
+    }
+
+    // Plugs a link cable into the serial port, such as a `TcpLink` to
+    // another emulator.
+    pub fn set_link(&mut self, link: impl Link + 'static) {
+        self.bus.set_link(Some(Rc::new(RefCell::new(link))));
+    }
+
+    // Keeps every byte sent over the serial port, where test ROMs print
+    // their results, for `Memory::serial_output`. Off by default, as the log
+    // would otherwise grow for as long as a game talks over the link cable.
+    pub fn set_serial_capture(&mut self, enabled: bool) {
+        self.bus.set_serial_capture(enabled);
+    }
+}
+
+impl<B: Bus> CPU<B> {
+
+    pub fn with_bus(bus: B) -> CPU<B> {
         CPU {
             registers: Registers::new(),
-            memory: Memory::new(),
+            bus,
             enable_interrupts: false,
             ime: false,
-            halted: false
+            halted: false,
+            breakpoint: false,
+            extra_dots: 0,
+            presented_frame: 0
         }
     }
 
-    pub fn update(&mut self) {
-        let mut totalCycles = 0;
-        while totalCycles < 69905 {
-            let mut cycles = 0;
-
-            if self.halted && self.memory.get_interrupts().is_pending() {
-                self.halted = false;
-            }
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
 
-            if !self.halted {
-                if self.ime {
-                    let interrupt = self.handle_interrupts();
-                    if interrupt {
-                        cycles += 4;
-                    }
-                }
-            
-                let byte = self.memory.read(self.registers.read_pc());
-                self.registers.increase_pc();
-                cycles += self.decode(byte);
-                
-            } else {
-                cycles = 1;
-            }
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
 
-            self.memory.update_timer(cycles);
-            self.memory.update_gpu(cycles);
+    pub(crate) fn registers(&self) -> &Registers {
+        &self.registers
+    }
 
-            totalCycles += cycles as usize;
-        }
+    // Set when the CPU executes LD B,B, which test ROMs use as a software
+    // breakpoint. Reading it clears it.
+    pub fn take_breakpoint(&mut self) -> bool {
+        std::mem::take(&mut self.breakpoint)
     }
 
-    pub fn set_button(&mut self, button: Button) {
-        self.memory.set_button(button);
+    // Runs for the length of one frame, `DOTS_PER_FRAME` dots.
+    pub fn run_frame(&mut self) {
+        self.run_dots(DOTS_PER_FRAME);
     }
 
-    pub fn unset_button(&mut self, button: Button) {
-        self.memory.unset_button(button);
+    // Runs for the length of one line, `DOTS_PER_LINE` dots.
+    pub fn run_scanline(&mut self) {
+        self.run_dots(DOTS_PER_LINE);
     }
 
-    pub fn get_framebuffer(&self) -> [u8; 160*144*4] {
-        self.memory.get_gpu().framebuffer
+    // Instructions overshooting `dots` are paid back by the next call, so the
+    // emulated time matches the time asked for.
+    fn run_dots(&mut self, dots: u32) {
+        while self.extra_dots < dots {
+            let cycles = self.step_instruction();
+            self.extra_dots += cycles * self.bus.dots_per_cycle();
+        }
+        self.extra_dots -= dots;
     }
 
-    pub fn load_rom(&mut self, data: Vec<u8>) {
-        self.memory.load_rom(data);
+    // Runs one instruction, or one M-cycle while halted, returning the
+    // M-cycles it took.
+    pub fn step_instruction(&mut self) -> u32 {
+        let mut cycles = 0;
+
+        if self.halted && self.bus.is_interrupt_pending() {
+            self.halted = false;
+        }
+
+        if !self.halted {
+            if self.ime {
+                let interrupt = self.handle_interrupts();
+                if interrupt {
+                    cycles += 4;
+                }
+            }
+        
+            let byte = self.bus.read(self.registers.read_pc());
+            self.registers.increase_pc();
+            if byte == 0x40 {
+                self.breakpoint = true;
+            }
+            cycles += self.decode(byte);
+            
+        } else {
+            cycles = 1;
+        }
+
+        self.bus.tick(cycles);
+
+        // The CPU waits while VRAM DMA copies data, the rest of the system
+        // keeps running
+        let mut total = cycles as u32;
+        loop {
+            let stall = self.bus.take_stall_cycles();
+            if stall == 0 {
+                break;
+            }
+            for _ in 0..stall {
+                self.bus.tick(1);
+            }
+            total += stall as u32;
+        }
+
+        total
     }
 
     pub fn decode(&mut self, byte: u8) -> u8 {
@@ -109,25 +418,28 @@ impl CPU {
             },
             0x08 => {
                 // LD (u16), SP
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
                 let sp = self.registers.read_double_register(&DoubleRegister::SP);
-                self.memory.write(address, sp as u8);
-                self.memory.write(address + 1, (sp >> 8) as u8);
+                self.bus.write(address, sp as u8);
+                self.bus.write(address + 1, (sp >> 8) as u8);
                 5
             },
             0x10 => {
                 // STOP
-                // self.registers.increase_pc();
-                // 1
-                todo!()
+                self.registers.increase_pc();
+                if !self.bus.stop() {
+                    // Low power mode, left like HALT
+                    self.halted = true;
+                }
+                1
             },
             0x18 => {
                 // JR i8
-                let offset = self.memory.read(self.registers.read_pc());
+                let offset = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
 
                 self.registers.offset_pc(offset as i8);
@@ -135,7 +447,7 @@ impl CPU {
             },
             0x20 | 0x28 | 0x30 | 0x38 => {
                 // JR cond, i8
-                let offset = self.memory.read(self.registers.read_pc());
+                let offset = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
 
                 let cond = &FromPrimitive::from_u8((byte >> 3) & 0b00000011).unwrap();
@@ -150,9 +462,9 @@ impl CPU {
                 // LD r16, u16
                 let register = &FromPrimitive::from_u8((byte >> 4) & 0b00000011).unwrap();
                 
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
 
                 self.registers.write_double_register(register, (msb as u16) << 8 | (lsb as u16));
@@ -176,14 +488,14 @@ impl CPU {
 
                 let data = self.registers.read_register(&Register::A);
 
-                self.memory.write(self.registers.read_double_register_mem(dest_register), data);
+                self.bus.write(self.registers.read_double_register_mem(dest_register), data);
                 2
             },
             0x0A | 0x1A | 0x2A | 0x3A => {
                 // LD A, [r16mem]
                 let source_register = &FromPrimitive::from_u8((byte >> 4) & 0b00000011).unwrap();
 
-                let data = self.memory.read(self.registers.read_double_register_mem(source_register));
+                let data = self.bus.read(self.registers.read_double_register_mem(source_register));
                 self.registers.write_register(&Register::A, data);
                 2
             },
@@ -205,8 +517,8 @@ impl CPU {
 
                 let (cycles, old_value) = match register {
                     Register::HL => {
-                        let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                        self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), old.wrapping_add(1));
+                        let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                        self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), old.wrapping_add(1));
                         (3, old)                    
                     },
                     _ => {
@@ -227,8 +539,8 @@ impl CPU {
 
                 let (cycles, old_value) = match register {
                     Register::HL => {
-                        let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                        self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), old.wrapping_sub(1));
+                        let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                        self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), old.wrapping_sub(1));
                         (3, old)
                     },
                     _ => {
@@ -245,10 +557,10 @@ impl CPU {
             0x06 | 0x0E | 0x16 | 0x1E | 0x26 | 0x2E | 0x36 | 0x3E => {
                 // LD r8, u8
                 let register = &FromPrimitive::from_u8((byte >> 3) & 0b00000111).unwrap();
-                let n = self.memory.read(self.registers.read_pc());
+                let n = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 if let Register::HL = register {
-                    self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), n);
+                    self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), n);
                     3
                 } else { 
                     self.registers.write_register(register, n);
@@ -361,7 +673,7 @@ impl CPU {
                 let (cycles_source, data) = match source {
 
                     Register::HL => {
-                        let data = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
+                        let data = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
                         (2, data)
                     },
                     _ => {
@@ -371,7 +683,7 @@ impl CPU {
                 }; 
                 let cycles_dest = match dest {
                     Register::HL => {
-                        self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), data);
+                        self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), data);
                         2
                     },
                     _ => {
@@ -390,7 +702,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let a = self.registers.read_register(&Register::A);
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
 
@@ -409,7 +721,7 @@ impl CPU {
                 let a = self.registers.read_register(&Register::A);
                 let carry = self.registers.read_flag(&Flag::C) as u8;
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
 
@@ -427,7 +739,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let a = self.registers.read_register(&Register::A);
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
 
@@ -446,7 +758,7 @@ impl CPU {
                 let a = self.registers.read_register(&Register::A);
                 let carry = self.registers.read_flag(&Flag::C) as u8;
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
 
@@ -464,7 +776,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let a = self.registers.read_register(&Register::A);
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
                 let result = a & data;
@@ -482,7 +794,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let (cycles, data) = match register {
                     Register::HL => {
-                        let data = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
+                        let data = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
                         (2, data)
                     },
                     _ => {
@@ -504,7 +816,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let a = self.registers.read_register(&Register::A);
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
                 let result = a | data;
@@ -523,7 +835,7 @@ impl CPU {
                 let register = &FromPrimitive::from_u8(byte & 0b00000111).unwrap();
                 let a = self.registers.read_register(&Register::A);
                 let (cycles, data) = match register {
-                    Register::HL => (2, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL))),
+                    Register::HL => (2, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL))),
                     _ => (1, self.registers.read_register(register)),
                 };
 
@@ -542,9 +854,9 @@ impl CPU {
                 // RET cond
                 let condition = &FromPrimitive::from_u8((byte >> 3) & 0b00000111).unwrap();
                 if self.registers.check_condition(condition) {
-                    let low_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                    let low_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                     self.registers.increase_sp(1);
-                    let high_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                    let high_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                     self.registers.increase_sp(1);
                     self.registers.write_pc(((high_data as u16) << 8) | low_data as u16);
 
@@ -556,18 +868,18 @@ impl CPU {
             0xC1 | 0xD1 | 0xE1 | 0xF1 => {
                 // POP r16stk
                 let register = &FromPrimitive::from_u8((byte >> 4) & 0b00000011).unwrap();
-                let low_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let low_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
-                let high_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let high_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
                 self.registers.write_double_register_stack(register, ((high_data as u16) << 8) | low_data as u16);
                 3
             },
             0xC9 => {
                 // RET
-                let low_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let low_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
-                let high_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let high_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
                 self.registers.write_pc(((high_data as u16) << 8) | low_data as u16);
                 4
@@ -575,9 +887,9 @@ impl CPU {
             0xD9 => {
                 // RETI
                 self.enable_interrupts = true;
-                let low_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let low_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
-                let high_data = self.memory.read(self.registers.read_double_register(&DoubleRegister::SP));
+                let high_data = self.bus.read(self.registers.read_double_register(&DoubleRegister::SP));
                 self.registers.increase_sp(1);
                 self.registers.write_pc(((high_data as u16) << 8) | low_data as u16);
                 4
@@ -586,9 +898,9 @@ impl CPU {
             0xC2 | 0xCA | 0xD2 | 0xDA => {
                 // JP cond, u16
                 let condition = &FromPrimitive::from_u8((byte >> 3) & 0b00000011).unwrap();
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
                 
@@ -601,9 +913,9 @@ impl CPU {
             },
             0xC3 => {
                 // JP u16
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
                 self.registers.write_pc(address);
@@ -612,17 +924,17 @@ impl CPU {
             0xC4 | 0xCC | 0xD4 | 0xDC => {
                 // CALL cond, u16
                 let condition = &FromPrimitive::from_u8((byte >> 3) & 0b00000011).unwrap();
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
 
                 if self.registers.check_condition(condition) {
                     self.registers.decrement_sp(1);
-                    self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
+                    self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
                     self.registers.decrement_sp(1);
-                    self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
+                    self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
                     self.registers.write_pc(address);
                     6
                 } else {
@@ -634,28 +946,28 @@ impl CPU {
                 let register = &FromPrimitive::from_u8((byte >> 4) & 0b00000011).unwrap();
                 let data = self.registers.read_double_register_stack(register);
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (data >> 8) as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (data >> 8) as u8);
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), data as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), data as u8);
                 4
             },
             0xCD => {
                 // CALL u16
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
                 self.registers.write_pc(address);
                 6
             },
             0xC6 => {
                 // ADD A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
 
@@ -670,7 +982,7 @@ impl CPU {
             },
             0xCE => {
                 // ADC A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
                 let carry = self.registers.read_flag(&Flag::C) as u8;
@@ -686,7 +998,7 @@ impl CPU {
             },
             0xD6 => {
                 // SUB A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
 
@@ -701,7 +1013,7 @@ impl CPU {
             },
             0xDE => {
                 // SBC A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
                 let carry = self.registers.read_flag(&Flag::C) as u8;
@@ -717,7 +1029,7 @@ impl CPU {
             },
             0xE6 => {
                 // AND A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
                 let result = a & value;
@@ -732,7 +1044,7 @@ impl CPU {
             },
             0xEE => {
                 // XOR A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let result = self.registers.read_register(&Register::A) ^ value;
                 self.registers.write_register(&Register::A, result);
@@ -745,7 +1057,7 @@ impl CPU {
             },
             0xF6 => {
                 // OR A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
                 let result = a | value;
@@ -760,7 +1072,7 @@ impl CPU {
             },
             0xFE => {
                 // CP A, u8
-                let value = self.memory.read(self.registers.read_pc());
+                let value = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let a = self.registers.read_register(&Register::A);
                 let (result, overflow )= a.overflowing_sub(value);
@@ -776,32 +1088,32 @@ impl CPU {
                 let target= ((byte >> 3) & 0b111) as u16 * 8;
 
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (self.registers.read_pc() >> 8) as u8);
                 self.registers.decrement_sp(1);
-                self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
+                self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), self.registers.read_pc() as u8);
                 self.registers.write_pc(target);
                 
                 4
             },
             0xE0 => {
                 // LD (FF00+u8), A
-                let offset = self.memory.read(self.registers.read_pc());
+                let offset = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
 
-                self.memory.write(0xff00 + offset as u16, self.registers.read_register(&Register::A));
+                self.bus.write(0xff00 + offset as u16, self.registers.read_register(&Register::A));
                 3
             },
             0xF0 => {
                 // LD A, (FF00+u8)
-                let offset = self.memory.read(self.registers.read_pc());
+                let offset = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
 
-                self.registers.write_register(&Register::A, self.memory.read(0xff00 + offset as u16));
+                self.registers.write_register(&Register::A, self.bus.read(0xff00 + offset as u16));
                 3
             },
             0xE8 => {
                 // ADD SP, i8
-                let value = self.memory.read(self.registers.read_pc()) as i8 as i16;
+                let value = self.bus.read(self.registers.read_pc()) as i8 as i16;
                 self.registers.increase_pc();
                 let sp = self.registers.read_double_register(&DoubleRegister::SP);
 
@@ -816,7 +1128,7 @@ impl CPU {
             },
             0xF8 => {
                 // LD HL, SP+i8
-                let value = self.memory.read(self.registers.read_pc()) as i8 as i16;
+                let value = self.bus.read(self.registers.read_pc()) as i8 as i16;
                 self.registers.increase_pc();
                 let sp = self.registers.read_double_register(&DoubleRegister::SP);
                 let result = sp.wrapping_add_signed(value);
@@ -844,37 +1156,37 @@ impl CPU {
                 let a = self.registers.read_register(&Register::A);
                 let c = self.registers.read_register(&Register::C);
 
-                self.memory.write(0xff00 + c as u16, a);
+                self.bus.write(0xff00 + c as u16, a);
                 2
             },
             0xEA => {
                 // LD (u16), A
                 let a = self.registers.read_register(&Register::A);
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
 
-                self.memory.write(address, a);
+                self.bus.write(address, a);
                 4
             },
             0xF2 => {
                 // LD A, (FF00+C)
                 let c = self.registers.read_register(&Register::C);
 
-                self.registers.write_register(&Register::A, self.memory.read(0xff00 + c as u16));
+                self.registers.write_register(&Register::A, self.bus.read(0xff00 + c as u16));
                 2
             },
             0xFA => {
                 // LD A, (u16)
-                let lsb = self.memory.read(self.registers.read_pc());
+                let lsb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
-                let msb = self.memory.read(self.registers.read_pc());
+                let msb = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 let address = (msb as u16) << 8 | (lsb as u16);
 
-                self.registers.write_register(&Register::A, self.memory.read(address));
+                self.registers.write_register(&Register::A, self.bus.read(address));
                 4
             },
             0xF3 => {
@@ -892,7 +1204,7 @@ impl CPU {
                 Group CB
             */
             0xCB => {
-                let cb_instruction: u8 = self.memory.read(self.registers.read_pc());
+                let cb_instruction: u8 = self.bus.read(self.registers.read_pc());
                 self.registers.increase_pc();
                 
                 match cb_instruction {
@@ -901,8 +1213,8 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, result) = match register {
                             Register::HL => {
-                                let r = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL)).rotate_left(1);
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), r);
+                                let r = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL)).rotate_left(1);
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), r);
                                 (4, r)
                             },
                             _ => {
@@ -922,9 +1234,9 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, result) = match register {
                             Register::HL => {
-                                let r = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                let r = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
                                 let result = r.rotate_right(1);
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), result);
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), result);
                                 (4, result)
                             },
                             _ => {
@@ -946,9 +1258,9 @@ impl CPU {
                         let flag = self.registers.read_flag(&Flag::C);
                         let (cycles, old, result) = match register {
                             Register::HL => {
-                                let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
                                 let result = (old << 1) | flag as u8;
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), result);
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), result);
                                 (4, old, result)
                             },
                             _ => {
@@ -971,9 +1283,9 @@ impl CPU {
                         let flag = self.registers.read_flag(&Flag::C);
                         let (cycles, old, result) = match register {
                             Register::HL => {
-                                let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
                                 let result = (old >> 1) | (flag as u8) << 7;
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), result);
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), result);
                                 (4, old, result)
                             },
                             _ => {
@@ -995,8 +1307,8 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, old_value) = match register {
                             Register::HL => {
-                                let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), old << 1);
+                                let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), old << 1);
                                 (4, old)
                             },
                             _ => {
@@ -1016,8 +1328,8 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, old_value) = match register {
                             Register::HL => {
-                                let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), (old >> 1) | (old & 0b10000000) );
+                                let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), (old >> 1) | (old & 0b10000000) );
                                 (4, old)
                             },
                             _ => {
@@ -1037,8 +1349,8 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, old_value) = match register {
                             Register::HL => {
-                                let r = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), ((r & 0x0f) << 4) | (r >> 4));
+                                let r = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), ((r & 0x0f) << 4) | (r >> 4));
                                 (4, r)
                             },
                             _ => {
@@ -1058,8 +1370,8 @@ impl CPU {
                         let register = &FromPrimitive::from_u8(cb_instruction & 0b00000111).unwrap();
                         let (cycles, old_value) = match register {
                             Register::HL => {
-                                let old = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                                self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), old >> 1);
+                                let old = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                                self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), old >> 1);
                                 (4, old)
                             },
                             _ => {
@@ -1081,7 +1393,7 @@ impl CPU {
 
                         let (cycles, value) =  match register {
                             Register::HL => {
-                                (3, self.memory.read(self.registers.read_double_register(&DoubleRegister::HL)))
+                                (3, self.bus.read(self.registers.read_double_register(&DoubleRegister::HL)))
                             },
                             _ => {
                                 (2, self.registers.read_register(register))
@@ -1099,8 +1411,8 @@ impl CPU {
                         let bit = (cb_instruction >> 3) & 0b00000111;
 
                         if let Register::HL = register {
-                            let value = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                            self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), value & !(1 << bit));
+                            let value = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                            self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), value & !(1 << bit));
                             4
                         } else {
                             self.registers.write_register(register, self.registers.read_register(register) & !(1 << bit));
@@ -1113,8 +1425,8 @@ impl CPU {
                         let bit = (cb_instruction >> 3) & 0b00000111;
 
                         if let Register::HL = register {
-                            let value = self.memory.read(self.registers.read_double_register(&DoubleRegister::HL));
-                            self.memory.write(self.registers.read_double_register(&DoubleRegister::HL), value | (1 << bit));
+                            let value = self.bus.read(self.registers.read_double_register(&DoubleRegister::HL));
+                            self.bus.write(self.registers.read_double_register(&DoubleRegister::HL), value | (1 << bit));
                             4
                         } else {
                             self.registers.write_register(register, self.registers.read_register(register) | (1 << bit));
@@ -1131,34 +1443,32 @@ impl CPU {
     fn jump_interrupt(&mut self, target: u16) {
         let pc = self.registers.read_pc();
         self.registers.decrement_sp(1);
-        self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (pc >> 8) as u8);
+        self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (pc >> 8) as u8);
         self.registers.decrement_sp(1);
-        self.memory.write(self.registers.read_double_register(&DoubleRegister::SP), (pc & 0xff) as u8);
+        self.bus.write(self.registers.read_double_register(&DoubleRegister::SP), (pc & 0xff) as u8);
         self.registers.write_pc(target);
     }
 
     fn handle_interrupts(&mut self) -> bool {
 
-        let mut interrupted = true;
-        if self.memory.get_interrupts().is_enabled_and_requested(InterruptHandler::VBlank){
-            self.jump_interrupt(0x40);
-            self.memory.get_interrupts().unset_if_bit(InterruptHandler::VBlank);
-        } else if self.memory.get_interrupts().is_enabled_and_requested(InterruptHandler::LCD) {
-            self.jump_interrupt(0x48);
-            self.memory.get_interrupts().unset_if_bit(InterruptHandler::LCD);
-        } else if self.memory.get_interrupts().is_enabled_and_requested(InterruptHandler::Timer) {
-            self.jump_interrupt(0x50);
-            self.memory.get_interrupts().unset_if_bit(InterruptHandler::Timer);
-        } else if self.memory.get_interrupts().is_enabled_and_requested(InterruptHandler::Serial){
-            self.jump_interrupt(0x58);
-            self.memory.get_interrupts().unset_if_bit(InterruptHandler::Serial);
-        } else if self.memory.get_interrupts().is_enabled_and_requested(InterruptHandler::Joypad) {
-            self.jump_interrupt(0x60);
-            self.memory.get_interrupts().unset_if_bit(InterruptHandler::Joypad);
+        let pending = self.bus.read_interrupt_enable() & self.bus.read_interrupt_flag();
+
+        let interrupt = if pending & (1 << InterruptHandler::VBlank as u8) != 0 {
+            InterruptHandler::VBlank
+        } else if pending & (1 << InterruptHandler::LCD as u8) != 0 {
+            InterruptHandler::LCD
+        } else if pending & (1 << InterruptHandler::Timer as u8) != 0 {
+            InterruptHandler::Timer
+        } else if pending & (1 << InterruptHandler::Serial as u8) != 0 {
+            InterruptHandler::Serial
+        } else if pending & (1 << InterruptHandler::Joypad as u8) != 0 {
+            InterruptHandler::Joypad
         } else {
-            interrupted = false;
-        }
+            return false;
+        };
 
-        interrupted
+        self.jump_interrupt(interrupt.vector());
+        self.bus.acknowledge_interrupt(interrupt);
+        true
     }
 }
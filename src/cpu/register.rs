@@ -18,7 +18,7 @@ impl Register {
         Register(value)
     }
 
-    pub fn to_u8(&self) -> u8 {
+    pub fn to_u8(self) -> u8 {
         self.0
     }
 
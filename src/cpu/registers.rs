@@ -1,5 +1,9 @@
+use std::io;
+
 use num_derive::FromPrimitive;
 
+use super::state::{SaveState, StateReader, StateWriter};
+
 #[derive(FromPrimitive)]
 pub enum Register {
     B = 0b000,
@@ -52,6 +56,7 @@ pub enum Condition {
 }
 
 
+#[derive(Clone)]
 pub struct Registers {
     b: u8,
     c: u8,
@@ -257,3 +262,20 @@ impl Registers {
         self.sp = self.sp.wrapping_sub(value);
     }
 }
+
+impl SaveState for Registers {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bytes(&[self.b, self.c, self.d, self.e, self.h, self.l, self.a, self.f]);
+        state.u16(self.sp);
+        state.u16(self.pc);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        let mut registers = [0; 8];
+        state.bytes(&mut registers)?;
+        [self.b, self.c, self.d, self.e, self.h, self.l, self.a, self.f] = registers;
+        self.sp = state.u16()?;
+        self.pc = state.u16()?;
+        Ok(())
+    }
+}
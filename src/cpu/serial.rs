@@ -0,0 +1,191 @@
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use super::link::Link;
+use super::state::{SaveState, StateReader, StateWriter};
+
+// M-cycles per bit with the 8192 Hz internal clock, and with the 262144 Hz
+// clock CGBs can select. Both double with the CPU in double speed mode.
+const CYCLES_PER_BIT: u16 = 128;
+const FAST_CYCLES_PER_BIT: u16 = 4;
+
+// M-cycles between checks of the link cable for data from the other side
+const LINK_POLL_CYCLES: u16 = 64;
+
+#[derive(Clone)]
+pub struct Serial {
+    // FF01 - SB: Serial transfer data
+    sb: u8,
+    // FF02 - SC: Serial transfer control
+    sc: u8,
+    // Bits left to shift out by an internal clock transfer, and M-cycles
+    // until the next one
+    bits: u8,
+    cycles: u16,
+    cgb: bool,
+
+    // Shared by the clones made while loading a state
+    link: Option<Rc<RefCell<dyn Link>>>,
+    link_cycles: u16,
+
+    // Bytes sent with the internal clock, only kept once capture is enabled
+    output: Option<Vec<u8>>
+}
+
+impl Serial {
+
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits: 0,
+            cycles: 0,
+            cgb: false,
+            link: None,
+            link_cycles: 0,
+            output: None
+        }
+    }
+
+    pub fn set_cgb_mode(&mut self, enabled: bool) {
+        self.cgb = enabled;
+    }
+
+    pub fn set_capture(&mut self, enabled: bool) {
+        self.output = if enabled { Some(self.output.take().unwrap_or_default()) } else { None };
+    }
+
+    pub fn set_link(&mut self, link: Option<Rc<RefCell<dyn Link>>>) {
+        self.link = link;
+    }
+
+    pub fn read_sb(&self) -> u8 {
+        self.sb
+    }
+
+    pub fn write_sb(&mut self, value: u8) {
+        self.sb = value;
+    }
+
+    pub fn read_sc(&self) -> u8 {
+        if self.cgb {
+            self.sc | 0b01111100
+        } else {
+            self.sc | 0b01111110
+        }
+    }
+
+    pub fn write_sc(&mut self, value: u8) {
+        self.sc = value;
+
+        if self.is_internal_transfer() {
+            if let Some(output) = &mut self.output {
+                output.push(self.sb);
+            }
+            self.bits = 8;
+            self.cycles = self.cycles_per_bit();
+            if let Some(link) = &self.link {
+                link.borrow_mut().start_transfer(self.sb);
+            }
+        }
+    }
+
+    fn is_internal_transfer(&self) -> bool {
+        self.sc & 0x81 == 0x81
+    }
+
+    fn is_external_transfer(&self) -> bool {
+        self.sc & 0x81 == 0x80
+    }
+
+    fn cycles_per_bit(&self) -> u16 {
+        if self.cgb && (self.sc >> 1) & 1 == 1 {
+            FAST_CYCLES_PER_BIT
+        } else {
+            CYCLES_PER_BIT
+        }
+    }
+
+    fn complete_transfer(&mut self, data: u8) -> bool {
+        self.sb = data;
+        self.sc &= !0x80;
+        true
+    }
+
+    // Returns whether a transfer completed and the serial interrupt has to
+    // be requested.
+    pub fn update(&mut self, cycles: u8) -> bool {
+        let mut remaining = cycles as u16;
+        while self.is_internal_transfer() && self.bits > 0 && remaining > 0 {
+            let elapsed = remaining.min(self.cycles);
+            remaining -= elapsed;
+            self.cycles -= elapsed;
+            if self.cycles == 0 {
+                // Shifts in ones until the byte of the other side is known
+                self.sb = self.sb << 1 | 1;
+                self.bits -= 1;
+                self.cycles = self.cycles_per_bit();
+            }
+        }
+
+        if self.link.is_none() {
+            // Nothing is plugged into the link port: internal clock transfers
+            // end with all ones, external clock ones never do
+            return self.is_internal_transfer() && self.bits == 0 && self.complete_transfer(0xFF);
+        }
+
+        self.link_cycles += cycles as u16;
+        if self.link_cycles < LINK_POLL_CYCLES {
+            return false;
+        }
+        self.link_cycles = 0;
+        self.poll_link()
+    }
+
+    fn poll_link(&mut self) -> bool {
+        let Some(link) = self.link.clone() else {
+            return false;
+        };
+        let mut link = link.borrow_mut();
+
+        // Transfers from the other side are always answered, so it never
+        // waits forever, but only shift in while ours waits on its clock
+        if let Some(data) = link.poll_transfer() {
+            link.reply(self.sb);
+            if self.is_external_transfer() {
+                return self.complete_transfer(data);
+            }
+        }
+
+        if self.is_internal_transfer() && self.bits == 0 {
+            if let Some(data) = link.poll_reply() {
+                return self.complete_transfer(data);
+            }
+        }
+        false
+    }
+
+    pub fn output(&self) -> &[u8] {
+        self.output.as_deref().unwrap_or_default()
+    }
+}
+
+// The output log and the link cable are not part of the machine and stay as
+// they are.
+impl SaveState for Serial {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.sb);
+        state.u8(self.sc);
+        state.u8(self.bits);
+        state.u16(self.cycles);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.sb = state.u8()?;
+        self.sc = state.u8()?;
+        self.bits = state.u8()?.min(8);
+        self.cycles = state.u16()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,527 @@
+use std::io;
+
+use super::gpu::Palette;
+use super::state::{invalid_data, SaveState, StateReader, StateWriter};
+
+pub const BORDER_WIDTH: usize = 256;
+pub const BORDER_HEIGHT: usize = 224;
+
+const SCREEN_WIDTH: usize = 160;
+const SCREEN_HEIGHT: usize = 144;
+// Top-left corner of the game screen inside the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// Attribute map of 20x18 cells of 8x8 pixels
+const CELLS_X: usize = 20;
+const CELLS_Y: usize = 18;
+
+// Commands
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mask {
+    Cancel,
+    Freeze,
+    Black,
+    Color0
+}
+
+// Super Game Boy: receives command packets sent by the game through P14/P15
+// and colours the screen with them, optionally inside a border.
+#[derive(Clone)]
+pub struct Sgb {
+    // Packet being received, bit by bit
+    receiving: bool,
+    bit: usize,
+    packet: [u8; 16],
+    last_pulse: u8,
+    // Packets of a multi-packet command received so far
+    command: Vec<u8>,
+    // VRAM transfer command waiting for the next frame
+    pending_transfer: Option<(u8, u8)>,
+
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u8>,
+    attributes: [u8; CELLS_X * CELLS_Y],
+    attribute_files: Vec<u8>,
+    mask: Mask,
+
+    players: u8,
+    player: u8,
+
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: [[u16; 16]; 4],
+
+    screen: [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
+}
+
+fn rgb555_to_rgb888(color: u16) -> [u8; 4] {
+    let expand = |c: u16| ((c << 3) | (c >> 2)) as u8;
+    [expand(color & 0x1F), expand((color >> 5) & 0x1F), expand((color >> 10) & 0x1F), 0xFF]
+}
+
+fn read_u16(data: &[u8], index: usize) -> u16 {
+    u16::from_le_bytes([data[index], data[index + 1]])
+}
+
+impl Sgb {
+
+    pub fn new() -> Sgb {
+        // Like the SGB BIOS before the game sends any palette
+        let grey = [0x7FFF, 0x56B5, 0x294A, 0x0000];
+
+        Sgb {
+            receiving: false,
+            bit: 0,
+            packet: [0; 16],
+            last_pulse: 0b11,
+            command: Vec::with_capacity(16 * 7),
+            pending_transfer: None,
+            palettes: [grey; 4],
+            system_palettes: vec![0; 0x1000],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attribute_files: vec![0; 0x1000],
+            mask: Mask::Cancel,
+            players: 1,
+            player: 0,
+            border_tiles: vec![0; 0x2000],
+            border_map: vec![0; 0x800],
+            border_palettes: [[0; 16]; 4],
+            screen: [0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
+        }
+    }
+
+    // Colourised game screen.
+    pub fn screen(&self) -> &[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4] {
+        &self.screen
+    }
+
+    // Writes to P1 carry the packets: P14 and P15 low together reset the
+    // transfer, then each bit is a pulse of P14 (0) or P15 (1) going low.
+    // 128 bits make a packet, followed by a 0 stop bit.
+    pub fn write_joypad(&mut self, value: u8) {
+        let pulse = (value >> 4) & 0b11;
+        if pulse == self.last_pulse {
+            return;
+        }
+        let previous = std::mem::replace(&mut self.last_pulse, pulse);
+
+        match pulse {
+            0b00 => {
+                self.receiving = true;
+                self.bit = 0;
+                self.packet = [0; 16];
+            },
+            0b01 | 0b10 if self.receiving && previous == 0b11 => {
+                if self.bit == 128 {
+                    self.receiving = false;
+                    self.receive_packet();
+                } else {
+                    if pulse == 0b01 {
+                        self.packet[self.bit / 8] |= 1 << (self.bit % 8);
+                    }
+                    self.bit += 1;
+                }
+            },
+            // With multiplayer enabled, releasing P15 selects the next
+            // controller
+            0b11 if !self.receiving && previous & 0b10 == 0 && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            },
+            _ => {}
+        }
+    }
+
+    // With multiplayer enabled and no row selected, the low nibble tells the
+    // game which controller is being read. Controllers 2-4 are never pressed.
+    pub fn read_joypad(&self, value: u8) -> u8 {
+        if self.players == 1 {
+            value
+        } else if value & 0x30 == 0x30 {
+            (value & 0xF0) | (0x0F - self.player)
+        } else if self.player != 0 {
+            value | 0x0F
+        } else {
+            value
+        }
+    }
+
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+
+        let length = (self.command[0] & 0b111).max(1) as usize;
+        if self.command.len() >= length * 16 {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palette_pair(data, 0, 1),
+            PAL23 => self.set_palette_pair(data, 2, 3),
+            PAL03 => self.set_palette_pair(data, 0, 3),
+            PAL12 => self.set_palette_pair(data, 1, 2),
+            ATTR_BLK => self.attribute_blocks(data),
+            ATTR_LIN => self.attribute_lines(data),
+            ATTR_DIV => self.attribute_division(data),
+            ATTR_CHR => self.attribute_characters(data),
+            PAL_SET => self.set_system_palettes(data),
+            MLT_REQ => {
+                self.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1
+                };
+                self.player = 0;
+            },
+            ATTR_SET => self.apply_attribute_file(data[1]),
+            MASK_EN => {
+                self.mask = match data[1] & 0b11 {
+                    0 => Mask::Cancel,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0
+                };
+            },
+            command @ (PAL_TRN | CHR_TRN | PCT_TRN | ATTR_TRN) => {
+                self.pending_transfer = Some((command, data[1]));
+            },
+            // Sound, SNES and other commands are not emulated
+            _ => {}
+        }
+    }
+
+    fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        // Colour 0 is shared by all the palettes
+        let color_0 = read_u16(data, 1);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+        for color in 1..4 {
+            self.palettes[first][color] = read_u16(data, 1 + color * 2);
+            self.palettes[second][color] = read_u16(data, 7 + color * 2);
+        }
+    }
+
+    fn set_cell(&mut self, x: usize, y: usize, palette: u8) {
+        if x < CELLS_X && y < CELLS_Y {
+            self.attributes[y * CELLS_X + x] = palette & 0b11;
+        }
+    }
+
+    fn attribute_blocks(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min(18);
+
+        for block in data[2..].chunks_exact(6).take(count) {
+            let control = block[0] & 0b111;
+            let inside = block[1] & 0b11;
+            let outside = (block[1] >> 4) & 0b11;
+            // Only inside or only outside also colours the border
+            let border = match control {
+                0b001 => inside,
+                0b100 => outside,
+                _ => (block[1] >> 2) & 0b11
+            };
+            let (x1, y1, x2, y2) = (block[2] as usize, block[3] as usize, block[4] as usize, block[5] as usize);
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within = x >= x1 && x <= x2 && y >= y1 && y <= y2;
+                    let on_border = within && (x == x1 || x == x2 || y == y1 || y == y2);
+
+                    if on_border {
+                        if control & 0b010 != 0 || control == 0b001 || control == 0b100 {
+                            self.set_cell(x, y, border);
+                        }
+                    } else if within {
+                        if control & 0b001 != 0 {
+                            self.set_cell(x, y, inside);
+                        }
+                    } else if control & 0b100 != 0 {
+                        self.set_cell(x, y, outside);
+                    }
+                }
+            }
+        }
+    }
+
+    fn attribute_lines(&mut self, data: &[u8]) {
+        let count = (data[1] as usize).min(110);
+
+        for &line in data[2..].iter().take(count) {
+            let number = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0b11;
+            if (line >> 7) & 1 == 1 {
+                (0..CELLS_X).for_each(|x| self.set_cell(x, number, palette));
+            } else {
+                (0..CELLS_Y).for_each(|y| self.set_cell(number, y, palette));
+            }
+        }
+    }
+
+    fn attribute_division(&mut self, data: &[u8]) {
+        let after = data[1] & 0b11;
+        let before = (data[1] >> 2) & 0b11;
+        let on_line = (data[1] >> 4) & 0b11;
+        let horizontal = (data[1] >> 6) & 1 == 1;
+        let line = data[2] as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&line) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after
+                };
+                self.set_cell(x, y, palette);
+            }
+        }
+    }
+
+    fn attribute_characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = (read_u16(data, 3) as usize).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 1 == 1;
+
+        for index in 0..count {
+            let Some(byte) = data.get(6 + index / 4) else {
+                break;
+            };
+            let palette = byte >> (6 - (index % 4) * 2);
+            self.set_cell(x, y, palette);
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for palette in 0..4 {
+            let index = (read_u16(data, 1 + palette * 2) & 0x1FF) as usize * 8;
+            for color in 0..4 {
+                self.palettes[palette][color] = read_u16(&self.system_palettes, index + color * 2);
+            }
+        }
+        // Colour 0 of palette 0 is used by all of them
+        let color_0 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color_0;
+        }
+
+        if (data[9] >> 7) & 1 == 1 {
+            self.apply_attribute_file(data[9]);
+        }
+        if (data[9] >> 6) & 1 == 1 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    // Bits 0-5 select one of the 45 files of 90 bytes, bit 6 cancels the
+    // mask.
+    fn apply_attribute_file(&mut self, value: u8) {
+        let file = (value & 0x3F) as usize;
+        if file < 45 {
+            let data = &self.attribute_files[file * 90..file * 90 + 90];
+            for (cell, attribute) in self.attributes.iter_mut().enumerate() {
+                *attribute = (data[cell / 4] >> (6 - (cell % 4) * 2)) & 0b11;
+            }
+        }
+        if (value >> 6) & 1 == 1 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    pub fn has_pending_transfer(&self) -> bool {
+        self.pending_transfer.is_some()
+    }
+
+    // Completes a VRAM transfer with the 4 KiB of tile data shown on screen.
+    pub fn transfer(&mut self, data: &[u8]) {
+        let Some((command, argument)) = self.pending_transfer.take() else {
+            return;
+        };
+
+        match command {
+            PAL_TRN => self.system_palettes.copy_from_slice(&data[..0x1000]),
+            CHR_TRN => {
+                let offset = (argument & 1) as usize * 0x1000;
+                self.border_tiles[offset..offset + 0x1000].copy_from_slice(&data[..0x1000]);
+            },
+            PCT_TRN => {
+                self.border_map.copy_from_slice(&data[..0x800]);
+                for (palette, colors) in self.border_palettes.iter_mut().enumerate() {
+                    for (color, value) in colors.iter_mut().enumerate() {
+                        *value = read_u16(data, 0x800 + palette * 32 + color * 2);
+                    }
+                }
+            },
+            ATTR_TRN => self.attribute_files[..0xFD2].copy_from_slice(&data[..0xFD2]),
+            _ => {}
+        }
+    }
+
+    // Colours a finished DMG frame drawn with `Palette::GREY`.
+    pub fn colorize(&mut self, framebuffer: &[u8]) {
+        match self.mask {
+            Mask::Freeze => {},
+            Mask::Black => self.screen.fill(0),
+            Mask::Color0 => {
+                let color = rgb555_to_rgb888(self.palettes[0][0]);
+                for pixel in self.screen.chunks_exact_mut(4) {
+                    pixel.copy_from_slice(&color);
+                }
+            },
+            Mask::Cancel => {
+                for (index, pixel) in framebuffer.chunks_exact(4).enumerate() {
+                    let shade = Palette::GREY.0.iter()
+                        .position(|&(r, g, b)| [r, g, b] == pixel[..3])
+                        .unwrap_or(0);
+                    let (x, y) = (index % SCREEN_WIDTH, index / SCREEN_WIDTH);
+                    let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+
+                    let color = rgb555_to_rgb888(self.palettes[palette][shade]);
+                    self.screen[index * 4..index * 4 + 4].copy_from_slice(&color);
+                }
+            }
+        }
+    }
+
+    // 256x224 RGBA image of the border with the game screen in the middle.
+    pub fn render_border(&self) -> Vec<u8> {
+        let mut frame = vec![0; BORDER_WIDTH * BORDER_HEIGHT * 4];
+
+        let backdrop = rgb555_to_rgb888(self.palettes[0][0]);
+        for pixel in frame.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&backdrop);
+        }
+
+        for (y, row) in self.screen.chunks_exact(SCREEN_WIDTH * 4).enumerate() {
+            let start = ((SCREEN_Y + y) * BORDER_WIDTH + SCREEN_X) * 4;
+            frame[start..start + SCREEN_WIDTH * 4].copy_from_slice(row);
+        }
+
+        // 32x28 map of 4bpp SNES tiles, colour 0 is transparent
+        for (cell, entry) in self.border_map.chunks_exact(2).take(32 * 28).enumerate() {
+            let entry = u16::from_le_bytes([entry[0], entry[1]]);
+            let tile = &self.border_tiles[(entry & 0xFF) as usize * 32..][..32];
+            let palette = &self.border_palettes[((entry >> 10) & 0b11) as usize];
+            let x_flip = (entry >> 14) & 1 == 1;
+            let y_flip = (entry >> 15) & 1 == 1;
+
+            for line in 0..8 {
+                let row = if y_flip { 7 - line } else { line };
+                let planes = [tile[row * 2], tile[row * 2 + 1], tile[16 + row * 2], tile[16 + row * 2 + 1]];
+
+                for pixel in 0..8 {
+                    let bit = if x_flip { pixel } else { 7 - pixel };
+                    let color = planes.iter().enumerate()
+                        .fold(0, |color, (plane, data)| color | ((data >> bit) & 1) << plane);
+                    if color == 0 {
+                        continue;
+                    }
+
+                    let x = (cell % 32) * 8 + pixel;
+                    let y = (cell / 32) * 8 + line;
+                    let index = (y * BORDER_WIDTH + x) * 4;
+                    frame[index..index + 4].copy_from_slice(&rgb555_to_rgb888(palette[color as usize]));
+                }
+            }
+        }
+
+        frame
+    }
+}
+
+impl SaveState for Sgb {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.bool(self.receiving);
+        state.u8(self.bit as u8);
+        state.bytes(&self.packet);
+        state.u8(self.last_pulse);
+        state.u8(self.command.len() as u8);
+        state.bytes(&self.command);
+        let (command, argument) = self.pending_transfer.unwrap_or((0xFF, 0));
+        state.bytes(&[command, argument]);
+
+        self.palettes.iter().flatten().for_each(|&color| state.u16(color));
+        state.bytes(&self.system_palettes);
+        state.bytes(&self.attributes);
+        state.bytes(&self.attribute_files);
+        state.u8(self.mask as u8);
+        state.bytes(&[self.players, self.player]);
+
+        state.bytes(&self.border_tiles);
+        state.bytes(&self.border_map);
+        self.border_palettes.iter().flatten().for_each(|&color| state.u16(color));
+        state.bytes(&self.screen);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.receiving = state.bool()?;
+        self.bit = (state.u8()? as usize).min(128);
+        state.bytes(&mut self.packet)?;
+        self.last_pulse = state.u8()? & 0b11;
+        self.command = vec![0; (state.u8()? as usize).min(16 * 6)];
+        state.bytes(&mut self.command)?;
+        let (command, argument) = (state.u8()?, state.u8()?);
+        self.pending_transfer = (command != 0xFF).then_some((command, argument));
+
+        for color in self.palettes.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        state.bytes(&mut self.system_palettes)?;
+        state.bytes(&mut self.attributes)?;
+        self.attributes.iter_mut().for_each(|palette| *palette &= 0b11);
+        state.bytes(&mut self.attribute_files)?;
+        self.mask = match state.u8()? {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(invalid_data("Invalid SGB mask"))
+        };
+        self.players = state.u8()?;
+        self.player = state.u8()?;
+        if !matches!(self.players, 1 | 2 | 4) || self.player >= self.players {
+            return Err(invalid_data("Invalid SGB controller"));
+        }
+
+        state.bytes(&mut self.border_tiles)?;
+        state.bytes(&mut self.border_map)?;
+        for color in self.border_palettes.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        state.bytes(&mut self.screen)?;
+        Ok(())
+    }
+}
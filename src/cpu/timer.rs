@@ -1,6 +1,10 @@
+use std::io;
+
 use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 
+use super::state::{SaveState, StateReader, StateWriter};
+
 #[derive(FromPrimitive)]
 enum Clock {
     C4096,
@@ -21,6 +25,7 @@ impl Clock {
 }
 
 
+#[derive(Clone)]
 pub struct Timer {
     div_counter: u8,
     timer_counter: u16,
@@ -45,10 +50,6 @@ impl Timer {
         FromPrimitive::from_u8(self.tac & 0b11).unwrap()
     }
 
-    fn set_frequency(&mut self, frequency: Clock) {
-        self.tac = (self.tac & !(0b11)) | (frequency as u8);
-    }
-
     pub fn read_div(&self) -> u8 {
         self.div
     }
@@ -101,3 +102,21 @@ impl Timer {
         }
     }
 }
+
+impl SaveState for Timer {
+    fn save_state(&self, state: &mut StateWriter) {
+        state.u8(self.div_counter);
+        state.u16(self.timer_counter);
+        state.bytes(&[self.div, self.tima, self.tma, self.tac]);
+    }
+
+    fn load_state(&mut self, state: &mut StateReader) -> io::Result<()> {
+        self.div_counter = state.u8()?;
+        self.timer_counter = state.u16()?;
+        self.div = state.u8()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()?;
+        Ok(())
+    }
+}
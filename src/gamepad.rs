@@ -0,0 +1,121 @@
+// Gamepad inputs that can be bound to buttons. Reading gamepads needs the
+// `gamepad` feature; without it no gamepad is ever found.
+#[derive(Clone, Copy)]
+pub enum PadInput {
+    Up,
+    Down,
+    Left,
+    Right,
+    StickUp,
+    StickDown,
+    StickLeft,
+    StickRight,
+    South,
+    East,
+    North,
+    West,
+    Start,
+    Select
+}
+
+impl PadInput {
+
+    pub fn from_name(name: &str) -> Option<PadInput> {
+        match name {
+            "PadUp" => Some(PadInput::Up),
+            "PadDown" => Some(PadInput::Down),
+            "PadLeft" => Some(PadInput::Left),
+            "PadRight" => Some(PadInput::Right),
+            "PadStickUp" => Some(PadInput::StickUp),
+            "PadStickDown" => Some(PadInput::StickDown),
+            "PadStickLeft" => Some(PadInput::StickLeft),
+            "PadStickRight" => Some(PadInput::StickRight),
+            "PadSouth" => Some(PadInput::South),
+            "PadEast" => Some(PadInput::East),
+            "PadNorth" => Some(PadInput::North),
+            "PadWest" => Some(PadInput::West),
+            "PadStart" => Some(PadInput::Start),
+            "PadSelect" => Some(PadInput::Select),
+            _ => None
+        }
+    }
+}
+
+#[cfg(feature = "gamepad")]
+pub use self::gilrs_gamepad::Gamepad;
+
+#[cfg(not(feature = "gamepad"))]
+pub use self::no_gamepad::Gamepad;
+
+#[cfg(feature = "gamepad")]
+mod gilrs_gamepad {
+    use gilrs::{Axis, Button, Gilrs};
+
+    use super::PadInput;
+
+    // How far the stick has to be pushed to count as a direction
+    const STICK_THRESHOLD: f32 = 0.5;
+
+    pub struct Gamepad {
+        gilrs: Gilrs
+    }
+
+    impl Gamepad {
+
+        pub fn new() -> Option<Gamepad> {
+            match Gilrs::new() {
+                Ok(gilrs) => Some(Gamepad { gilrs }),
+                Err(e) => {
+                    eprintln!("Cannot read gamepads: {}", e);
+                    None
+                }
+            }
+        }
+
+        // Catches up with the events since the last call, call once per
+        // frame before `is_held`.
+        pub fn poll(&mut self) {
+            while self.gilrs.next_event().is_some() {}
+        }
+
+        // Whether `input` is held on any connected gamepad.
+        pub fn is_held(&self, input: PadInput) -> bool {
+            self.gilrs.gamepads().any(|(_, pad)| match input {
+                PadInput::Up => pad.is_pressed(Button::DPadUp),
+                PadInput::Down => pad.is_pressed(Button::DPadDown),
+                PadInput::Left => pad.is_pressed(Button::DPadLeft),
+                PadInput::Right => pad.is_pressed(Button::DPadRight),
+                PadInput::StickUp => pad.value(Axis::LeftStickY) > STICK_THRESHOLD,
+                PadInput::StickDown => pad.value(Axis::LeftStickY) < -STICK_THRESHOLD,
+                PadInput::StickLeft => pad.value(Axis::LeftStickX) < -STICK_THRESHOLD,
+                PadInput::StickRight => pad.value(Axis::LeftStickX) > STICK_THRESHOLD,
+                PadInput::South => pad.is_pressed(Button::South),
+                PadInput::East => pad.is_pressed(Button::East),
+                PadInput::North => pad.is_pressed(Button::North),
+                PadInput::West => pad.is_pressed(Button::West),
+                PadInput::Start => pad.is_pressed(Button::Start),
+                PadInput::Select => pad.is_pressed(Button::Select)
+            })
+        }
+    }
+}
+
+#[cfg(not(feature = "gamepad"))]
+mod no_gamepad {
+    use super::PadInput;
+
+    pub struct Gamepad;
+
+    impl Gamepad {
+
+        pub fn new() -> Option<Gamepad> {
+            None
+        }
+
+        pub fn poll(&mut self) {}
+
+        pub fn is_held(&self, _input: PadInput) -> bool {
+            false
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use crate::cpu::{CPU, Renderer, DOTS_PER_FRAME};
+use crate::cpu::bus::Bus;
+use crate::cpu::registers::Register;
+use crate::movie::Movie;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// Grey levels used to draw the expected image in diff files.
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+// Registers expected by mooneye test ROMs when they hit LD B,B after passing.
+const FIBONACCI: [(Register, u8); 6] = [
+    (Register::B, 3),
+    (Register::C, 5),
+    (Register::D, 8),
+    (Register::E, 13),
+    (Register::H, 21),
+    (Register::L, 34)
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestResult {
+    Passed,
+    Failed,
+    Timeout
+}
+
+impl TestResult {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            TestResult::Passed => 0,
+            TestResult::Failed => 1,
+            TestResult::Timeout => 2
+        }
+    }
+}
+
+pub struct TestReport {
+    pub result: TestResult,
+    pub serial: String,
+    pub cycles: u64
+}
+
+// Runs a test ROM without a frontend until it reports a result over the
+// serial port (Blargg) or through the LD B,B register signature (mooneye),
+// giving up after `max_cycles` M-cycles.
+pub fn run_test_rom(rom: Vec<u8>, max_cycles: u64) -> TestReport {
+    let mut cpu = CPU::new();
+    cpu.load_rom(rom);
+    cpu.set_serial_capture(true);
+
+    let mut cycles: u64 = 0;
+    let mut serial_length = 0;
+    let mut result = TestResult::Timeout;
+
+    while cycles < max_cycles {
+        cycles += cpu.step_instruction() as u64;
+
+        if cpu.take_breakpoint() {
+            let registers = cpu.registers();
+            let fibonacci = FIBONACCI.iter().all(|(r, value)| registers.read_register(r) == *value);
+            result = if fibonacci { TestResult::Passed } else { TestResult::Failed };
+            break;
+        }
+
+        let serial = cpu.bus().serial_output();
+        if serial.len() != serial_length {
+            serial_length = serial.len();
+            let text = String::from_utf8_lossy(serial);
+            if text.contains("Passed") {
+                result = TestResult::Passed;
+                break;
+            }
+            if text.contains("Failed") {
+                result = TestResult::Failed;
+                break;
+            }
+        }
+    }
+
+    TestReport {
+        result,
+        serial: String::from_utf8_lossy(cpu.bus().serial_output()).into_owned(),
+        cycles
+    }
+}
+
+pub enum Reference {
+    Png(PathBuf),
+    Hash(u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScreenshotResult {
+    Match,
+    Mismatch { pixels: usize },
+    HashMismatch { actual: u64 }
+}
+
+pub fn run_frames(rom: Vec<u8>, frames: u64, renderer: Renderer) -> [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4] {
+    let mut cpu = CPU::new();
+    cpu.load_rom(rom);
+    cpu.set_renderer(renderer);
+
+    // Gives up after the time the frames should have taken, in case the ROM
+    // keeps the LCD disabled
+    let mut dots: u64 = 0;
+    while cpu.frame_count() < frames && dots < frames * DOTS_PER_FRAME as u64 {
+        dots += cpu.step_instruction() as u64 * cpu.bus().dots_per_cycle() as u64;
+    }
+
+    if cpu.is_frame_blank() {
+        [0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4]
+    } else {
+        cpu.get_framebuffer()
+    }
+}
+
+// Plays `movie` to its end with the renderer it was recorded with,
+// returning the last frame like `run_frames`. A crash recorded in the movie
+// happens again here.
+pub fn run_movie(rom: Vec<u8>, movie: &Movie) -> io::Result<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 4]> {
+    let mut cpu = CPU::new();
+    movie.power_on(&mut cpu, rom)?;
+
+    let mut frame = 0;
+    while movie.play_frame(frame, &mut cpu) {
+        cpu.run_until_vblank();
+        frame += 1;
+    }
+
+    if cpu.is_frame_blank() {
+        Ok([0xFF; SCREEN_WIDTH * SCREEN_HEIGHT * 4])
+    } else {
+        Ok(cpu.get_framebuffer())
+    }
+}
+
+// Screenshots are compared on the four DMG shades rather than on exact RGB
+// values, so references produced by other emulators or with another palette
+// still match.
+fn shade(r: u8, g: u8, b: u8) -> u8 {
+    let luminance = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+    match luminance {
+        0xE6..=0xFF => 0,
+        0x90..=0xE5 => 1,
+        0x30..=0x8F => 2,
+        _ => 3
+    }
+}
+
+fn framebuffer_shades(framebuffer: &[u8]) -> Vec<u8> {
+    framebuffer.chunks_exact(4).map(|p| shade(p[0], p[1], p[2])).collect()
+}
+
+// FNV-1a over the shades of every pixel.
+pub fn framebuffer_hash(framebuffer: &[u8]) -> u64 {
+    framebuffer_shades(framebuffer).iter().fold(0xCBF29CE484222325, |hash, shade| {
+        (hash ^ *shade as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+fn load_reference_shades(path: &Path) -> io::Result<Vec<u8>> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+
+    if info.width as usize != SCREEN_WIDTH || info.height as usize != SCREEN_HEIGHT {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Reference image must be {}x{}", SCREEN_WIDTH, SCREEN_HEIGHT)));
+    }
+
+    let pixels = &buffer[..info.buffer_size()];
+    let shades = match info.color_type {
+        png::ColorType::Rgb => pixels.chunks_exact(3).map(|p| shade(p[0], p[1], p[2])).collect(),
+        png::ColorType::Rgba => pixels.chunks_exact(4).map(|p| shade(p[0], p[1], p[2])).collect(),
+        png::ColorType::Grayscale => pixels.iter().map(|p| shade(*p, *p, *p)).collect(),
+        png::ColorType::GrayscaleAlpha => pixels.chunks_exact(2).map(|p| shade(p[0], p[0], p[0])).collect(),
+        png::ColorType::Indexed => unreachable!("Indexed images are expanded by the decoder")
+    };
+    Ok(shades)
+}
+
+pub fn save_png(path: &Path, width: usize, height: usize, rgba: &[u8]) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(rgba)?;
+    Ok(())
+}
+
+// Writes the expected image, the actual image and the differing pixels (in
+// red) side by side.
+fn save_diff(path: &Path, expected: &[u8], framebuffer: &[u8]) -> io::Result<()> {
+    let actual = framebuffer_shades(framebuffer);
+    let mut image = vec![0; SCREEN_WIDTH * 3 * SCREEN_HEIGHT * 4];
+
+    for (i, row) in image.chunks_exact_mut(SCREEN_WIDTH * 3 * 4).enumerate() {
+        for x in 0..SCREEN_WIDTH {
+            let index = i * SCREEN_WIDTH + x;
+            let expected_shade = SHADES[expected[index] as usize];
+            let actual_shade = SHADES[actual[index] as usize];
+            let diff = if expected[index] == actual[index] {
+                [0xC0 + actual_shade / 4; 3]
+            } else {
+                [0xFF, 0, 0]
+            };
+
+            row[x * 4..x * 4 + 4].copy_from_slice(&[expected_shade, expected_shade, expected_shade, 0xFF]);
+            row[(SCREEN_WIDTH + x) * 4..(SCREEN_WIDTH + x) * 4 + 4].copy_from_slice(&framebuffer[index * 4..index * 4 + 4]);
+            row[(SCREEN_WIDTH * 2 + x) * 4..(SCREEN_WIDTH * 2 + x) * 4 + 4].copy_from_slice(&[diff[0], diff[1], diff[2], 0xFF]);
+        }
+    }
+
+    save_png(path, SCREEN_WIDTH * 3, SCREEN_HEIGHT, &image)
+}
+
+pub fn compare_screenshot(framebuffer: &[u8], reference: &Reference, diff_path: Option<&Path>) -> io::Result<ScreenshotResult> {
+    match reference {
+        Reference::Hash(hash) => {
+            let actual = framebuffer_hash(framebuffer);
+            if actual == *hash {
+                Ok(ScreenshotResult::Match)
+            } else {
+                Ok(ScreenshotResult::HashMismatch { actual })
+            }
+        },
+        Reference::Png(path) => {
+            let expected = load_reference_shades(path)?;
+            let actual = framebuffer_shades(framebuffer);
+            let pixels = expected.iter().zip(actual.iter()).filter(|(e, a)| e != a).count();
+
+            if pixels == 0 {
+                return Ok(ScreenshotResult::Match);
+            }
+
+            if let Some(diff_path) = diff_path {
+                save_diff(diff_path, &expected, framebuffer)?;
+            }
+            Ok(ScreenshotResult::Mismatch { pixels })
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io;
+
+use crab_gb::bindings::Bindings;
+use crab_gb::cpu::CPU;
+use crab_gb::cpu::joypad::Button;
+
+use winit::keyboard::KeyCode;
+use winit_input_helper::WinitInputHelper;
+
+use crate::gamepad::{Gamepad, PadInput};
+
+// Keys that can be bound, found by their winit name
+const KEYS: [KeyCode; 86] = [
+    KeyCode::KeyA, KeyCode::KeyB, KeyCode::KeyC, KeyCode::KeyD, KeyCode::KeyE, KeyCode::KeyF, KeyCode::KeyG,
+    KeyCode::KeyH, KeyCode::KeyI, KeyCode::KeyJ, KeyCode::KeyK, KeyCode::KeyL, KeyCode::KeyM, KeyCode::KeyN,
+    KeyCode::KeyO, KeyCode::KeyP, KeyCode::KeyQ, KeyCode::KeyR, KeyCode::KeyS, KeyCode::KeyT, KeyCode::KeyU,
+    KeyCode::KeyV, KeyCode::KeyW, KeyCode::KeyX, KeyCode::KeyY, KeyCode::KeyZ,
+    KeyCode::Digit0, KeyCode::Digit1, KeyCode::Digit2, KeyCode::Digit3, KeyCode::Digit4,
+    KeyCode::Digit5, KeyCode::Digit6, KeyCode::Digit7, KeyCode::Digit8, KeyCode::Digit9,
+    KeyCode::Numpad0, KeyCode::Numpad1, KeyCode::Numpad2, KeyCode::Numpad3, KeyCode::Numpad4,
+    KeyCode::Numpad5, KeyCode::Numpad6, KeyCode::Numpad7, KeyCode::Numpad8, KeyCode::Numpad9,
+    KeyCode::NumpadAdd, KeyCode::NumpadSubtract, KeyCode::NumpadMultiply, KeyCode::NumpadDivide,
+    KeyCode::NumpadDecimal, KeyCode::NumpadEnter,
+    KeyCode::ArrowUp, KeyCode::ArrowDown, KeyCode::ArrowLeft, KeyCode::ArrowRight,
+    KeyCode::Space, KeyCode::Enter, KeyCode::Tab, KeyCode::Backspace,
+    KeyCode::ShiftLeft, KeyCode::ShiftRight, KeyCode::ControlLeft, KeyCode::ControlRight,
+    KeyCode::AltLeft, KeyCode::AltRight,
+    KeyCode::Insert, KeyCode::Delete, KeyCode::Home, KeyCode::End, KeyCode::PageUp, KeyCode::PageDown,
+    KeyCode::Backquote, KeyCode::Minus, KeyCode::Equal, KeyCode::BracketLeft, KeyCode::BracketRight,
+    KeyCode::Backslash, KeyCode::Semicolon, KeyCode::Quote, KeyCode::Comma, KeyCode::Period, KeyCode::Slash,
+    KeyCode::F10, KeyCode::F11, KeyCode::F12
+];
+
+enum Input {
+    Key(KeyCode),
+    Pad(PadInput)
+}
+
+fn input_from_name(name: &str) -> Option<Input> {
+    if let Some(input) = PadInput::from_name(name) {
+        return Some(Input::Pad(input));
+    }
+    KEYS.iter().find(|key| format!("{:?}", key) == name).map(|key| Input::Key(*key))
+}
+
+fn resolve(names: &[String], button: Button, inputs: &mut Vec<(Input, Button)>) -> io::Result<()> {
+    for name in names {
+        let input = input_from_name(name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Invalid binding: unknown input {}", name)))?;
+        inputs.push((input, button));
+    }
+    Ok(())
+}
+
+// Feeds the keyboard and gamepad inputs bound to each button to the joypad.
+pub struct Controls {
+    inputs: Vec<(Input, Button)>,
+    turbo: Vec<(Input, Button)>,
+    gamepad: Option<Gamepad>
+}
+
+impl Controls {
+
+    // Fails on key or gamepad input names that do not exist.
+    pub fn new(bindings: &Bindings) -> io::Result<Controls> {
+        let mut inputs = Vec::new();
+        let mut turbo = Vec::new();
+        for button in Button::ALL {
+            resolve(bindings.inputs(button), button, &mut inputs)?;
+            resolve(bindings.turbo_inputs(button), button, &mut turbo)?;
+        }
+
+        let uses_gamepad = inputs.iter().chain(turbo.iter()).any(|(input, _)| matches!(input, Input::Pad(_)));
+        let gamepad = if uses_gamepad { Gamepad::new() } else { None };
+
+        Ok(Controls { inputs, turbo, gamepad })
+    }
+
+    // One bit per button with a held input, at `Button as u8`.
+    fn pressed(&self, inputs: &[(Input, Button)], input: &WinitInputHelper) -> u8 {
+        inputs.iter()
+            .filter(|(bound, _)| match bound {
+                Input::Key(key) => input.key_held(*key),
+                Input::Pad(pad) => self.gamepad.as_ref().is_some_and(|gamepad| gamepad.is_held(*pad))
+            })
+            .fold(0, |pressed, (_, button)| pressed | 1 << *button as u8)
+    }
+
+    pub fn update(&mut self, input: &WinitInputHelper, cpu: &mut CPU) {
+        if let Some(gamepad) = &mut self.gamepad {
+            gamepad.poll();
+        }
+
+        cpu.set_pressed_buttons(self.pressed(&self.inputs, input));
+        cpu.set_turbo_buttons(self.pressed(&self.turbo, input));
+    }
+}
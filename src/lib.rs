@@ -0,0 +1,7 @@
+#![allow(clippy::upper_case_acronyms, clippy::new_without_default)]
+
+pub mod bindings;
+pub mod cpu;
+pub mod headless;
+pub mod movie;
+pub mod rewind;
@@ -1,8 +1,15 @@
-mod cpu;
+use std::{fs, env, process, sync::Arc};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use std::{fs, env, sync::Arc};
-
-use cpu::CPU;
+use crab_gb::bindings::Bindings;
+use crab_gb::cpu;
+use crab_gb::headless;
+use crab_gb::movie::Movie;
+use crab_gb::rewind::Rewind;
+use cpu::{CPU, Palette, Palettes, Renderer, BORDER_WIDTH, BORDER_HEIGHT, FRAME_RATE};
+use cpu::joypad::DEFAULT_TURBO_RATE;
+use cpu::link::TcpLink;
 
 use pixels::{SurfaceTexture, Pixels};
 use winit::{event_loop::EventLoop, dpi::LogicalSize};
@@ -11,33 +18,291 @@ use winit::keyboard::KeyCode;
 use game_loop::game_loop;
 use winit_input_helper::WinitInputHelper;
 
+use input::Controls;
+
+mod gamepad;
+mod input;
+
 const WIDTH: u32 = 160;
 const HEIGHT: u32 = 144;
 
+const DEFAULT_TEST_CYCLES: u64 = 200_000_000;
+
+// A snapshot every 4 frames, for about 40 seconds
+const REWIND_INTERVAL: u64 = 4;
+const REWIND_CAPACITY: usize = 600;
+
+// F1-F9 load the save state in slots 1-9, with Shift they save it.
+const STATE_SLOT_KEYS: [KeyCode; 9] = [
+    KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4, KeyCode::F5, KeyCode::F6, KeyCode::F7, KeyCode::F8, KeyCode::F9
+];
+
 fn read_rom(path: &str) -> Vec<u8> {
     fs::read(path).expect("File not found")
 }
 
+// Input movie being recorded from power-on, or played back
+enum MovieState {
+    Recording(Movie, PathBuf),
+    // The movie and the next frame to play
+    Playing(Movie, usize)
+}
 
 struct Game {
     pixels: Pixels,
     input: WinitInputHelper,
-    cpu: CPU
+    controls: Controls,
+    cpu: CPU,
+    rom_path: PathBuf,
+    rewind: Rewind,
+    // Backspace is held: step back through the rewind buffer instead of
+    // running
+    rewinding: bool,
+    // Emulation speed multiplier, None for as fast as possible
+    speed: Option<f64>,
+    // Tab is held: run as fast as possible
+    fast_forward: bool,
+    // Frames owed to keep up with real time
+    frame_budget: f64,
+    paused: bool,
+    movie: Option<MovieState>,
+    window_title: String
 }
 
 impl Game {
-    fn new(pixels: Pixels) -> Self {
-        Self { pixels, input: WinitInputHelper::new(), cpu: CPU::new() }
+    fn new(pixels: Pixels, rom_path: PathBuf, controls: Controls) -> Self {
+        Self {
+            pixels,
+            input: WinitInputHelper::new(),
+            controls,
+            cpu: CPU::new(),
+            rom_path,
+            rewind: Rewind::new(REWIND_INTERVAL, REWIND_CAPACITY),
+            rewinding: false,
+            speed: Some(1.0),
+            fast_forward: false,
+            frame_budget: 0.0,
+            paused: false,
+            movie: None,
+            window_title: String::new()
+        }
+    }
+
+    fn run_frame(&mut self, draw: bool) {
+        match &mut self.movie {
+            Some(MovieState::Recording(movie, _)) => movie.record_frame(&self.cpu),
+            Some(MovieState::Playing(movie, frame)) => {
+                if movie.play_frame(*frame, &mut self.cpu) {
+                    *frame += 1;
+                } else {
+                    println!("Movie over after {} frames", frame);
+                    self.movie = None;
+                }
+            },
+            None => {}
+        }
+        self.cpu.set_rendering(draw);
+        self.cpu.run_until_vblank();
+        self.rewind.record(&self.cpu);
+    }
+
+    // Emulates the frames due after `elapsed` seconds of real time. Only the
+    // last one is drawn, as it is the only one shown.
+    fn advance(&mut self, elapsed: f64) {
+        let speed = if self.fast_forward { None } else { self.speed };
+        match speed {
+            Some(speed) => {
+                self.frame_budget += elapsed * FRAME_RATE * speed;
+                let frames = self.frame_budget as u32;
+                self.frame_budget -= frames as f64;
+                for frame in 1..=frames {
+                    self.run_frame(frame == frames);
+                }
+            },
+            None => {
+                // As many frames as fit in the time step, leaving some time
+                // to draw the last one
+                let deadline = Instant::now() + Duration::from_secs_f64(elapsed * 0.8);
+                while Instant::now() < deadline {
+                    self.run_frame(false);
+                }
+                self.run_frame(true);
+                self.frame_budget = 0.0;
+            }
+        }
+    }
+
+    fn is_playing_movie(&self) -> bool {
+        matches!(self.movie, Some(MovieState::Playing(..)))
+    }
+
+    fn save_movie(&self) {
+        if let Some(MovieState::Recording(movie, path)) = &self.movie {
+            match movie.save(path) {
+                Ok(()) => println!("Saved {} frames of movie to {}", movie.len(), path.display()),
+                Err(e) => eprintln!("Cannot save movie: {}", e)
+            }
+        }
+    }
+
+    fn title(&self) -> String {
+        let status = match self.movie {
+            Some(MovieState::Recording(..)) => " - Recording",
+            Some(MovieState::Playing(..)) => " - Playing",
+            None => ""
+        };
+        format!("CRAB-GB [{}]{}", self.rom_path.display(), status)
+    }
+
+    // Save states live next to the ROM, as <rom>.ss1 to <rom>.ss9.
+    fn state_path(&self, slot: usize) -> PathBuf {
+        self.rom_path.with_extension(format!("ss{}", slot))
+    }
+
+    fn save_state(&self, slot: usize) {
+        let path = self.state_path(slot);
+        match self.cpu.save_state_file(&path) {
+            Ok(()) => println!("Saved state {} to {}", slot, path.display()),
+            Err(e) => eprintln!("Cannot save state {}: {}", slot, e)
+        }
+    }
+
+    fn load_state(&mut self, slot: usize) {
+        let path = self.state_path(slot);
+        match self.cpu.load_state_file(&path) {
+            Ok(()) => println!("Loaded state {} from {}", slot, path.display()),
+            Err(e) => eprintln!("Cannot load state {}: {}", slot, e)
+        }
+    }
+}
+
+fn run_test(args: &[String]) -> ! {
+    let path = args.first().expect("Usage: crab-gb test <rom> [max cycles]");
+    let max_cycles = args.get(1).map_or(DEFAULT_TEST_CYCLES, |c| c.parse().expect("Invalid cycle count"));
+
+    let report = headless::run_test_rom(read_rom(path), max_cycles);
+
+    println!("{}", report.serial);
+    println!("{:?} after {} cycles", report.result, report.cycles);
+    process::exit(report.result.exit_code());
+}
+
+fn run_screenshot(args: &[String], renderer: Renderer) -> ! {
+    if args.len() != 3 {
+        panic!("Usage: crab-gb screenshot <rom> <frames> <output png>");
+    }
+    let frames = args[1].parse().expect("Invalid frame count");
+
+    let framebuffer = headless::run_frames(read_rom(&args[0]), frames, renderer);
+    headless::save_png(Path::new(&args[2]), headless::SCREEN_WIDTH, headless::SCREEN_HEIGHT, &framebuffer).expect("Cannot write screenshot");
+
+    println!("{:016x}", headless::framebuffer_hash(&framebuffer));
+    process::exit(0);
+}
+
+fn run_compare(args: &[String], renderer: Renderer) -> ! {
+    if args.len() < 3 || args.len() > 4 {
+        panic!("Usage: crab-gb compare <rom> <frames> <reference png or hash> [diff png]");
+    }
+    let frames = args[1].parse().expect("Invalid frame count");
+    let reference = match u64::from_str_radix(&args[2], 16) {
+        Ok(hash) if args[2].len() == 16 => headless::Reference::Hash(hash),
+        _ => headless::Reference::Png(PathBuf::from(&args[2]))
+    };
+
+    let framebuffer = headless::run_frames(read_rom(&args[0]), frames, renderer);
+    let result = headless::compare_screenshot(&framebuffer, &reference, args.get(3).map(Path::new)).expect("Cannot compare screenshot");
+
+    println!("{:?}", result);
+    process::exit(if result == headless::ScreenshotResult::Match { 0 } else { 1 });
+}
+
+fn run_play(args: &[String]) -> ! {
+    if args.len() < 2 || args.len() > 3 {
+        panic!("Usage: crab-gb play <rom> <movie> [output png]");
+    }
+    let movie = Movie::load(Path::new(&args[1])).expect("Cannot load movie");
+
+    let framebuffer = headless::run_movie(read_rom(&args[0]), &movie).expect("Cannot play movie");
+    if let Some(path) = args.get(2) {
+        headless::save_png(Path::new(path), headless::SCREEN_WIDTH, headless::SCREEN_HEIGHT, &framebuffer).expect("Cannot write screenshot");
+    }
+
+    println!("Played {} frames", movie.len());
+    println!("{:016x}", headless::framebuffer_hash(&framebuffer));
+    process::exit(0);
+}
+
+fn load_palettes(value: &str) -> Palettes {
+    match Palette::from_name(value) {
+        Some(palette) => Palettes::new(palette),
+        None => Palettes::load(Path::new(value)).expect("Cannot load palette file")
     }
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+
+    let renderer = if args.iter().any(|a| a == "--fifo") {
+        Renderer::Fifo
+    } else {
+        Renderer::Scanline
+    };
+    let access_blocking = !args.iter().any(|a| a == "--no-access-blocking");
+    let cgb_compatibility = args.iter().any(|a| a == "--cgb");
+    let color_correction = args.iter().any(|a| a == "--color-correction");
+    let sgb_border = args.iter().any(|a| a == "--sgb-border");
+    let sgb = sgb_border || args.iter().any(|a| a == "--sgb");
+    let allow_opposing = args.iter().any(|a| a == "--allow-opposing");
+    args.retain(|a| !["--fifo", "--no-access-blocking", "--cgb", "--color-correction", "--sgb", "--sgb-border", "--allow-opposing"].contains(&a.as_str()));
+    let (width, height) = if sgb_border { (BORDER_WIDTH as u32, BORDER_HEIGHT as u32) } else { (WIDTH, HEIGHT) };
+
+    let mut option_value = |flag: &str| match args.iter().position(|a| a == flag) {
+        Some(index) => {
+            let value = args.get(index + 1).unwrap_or_else(|| panic!("Missing value for {}", flag)).clone();
+            args.drain(index..=index + 1);
+            Some(value)
+        },
+        None => None
+    };
+
+    // Palette name or palette file
+    let palettes = option_value("--palette").map_or_else(|| Palettes::new(Palette::GREY), |value| load_palettes(&value));
+    let bindings = option_value("--bindings")
+        .map_or_else(|| Ok(Bindings::new()), |value| Bindings::load(Path::new(&value)))
+        .expect("Cannot load bindings file");
+    // Frames each turbo press and release lasts
+    let turbo_rate = option_value("--turbo-rate").map_or(DEFAULT_TURBO_RATE, |value| value.parse().expect("Invalid turbo rate"));
+
+    // Record the input from power-on to a movie file, or play one back
+    let record = option_value("--record").map(PathBuf::from);
+    let play = option_value("--play").map(PathBuf::from);
+    // Link cable to another instance, one listening and the other connecting
+    let link_listen = option_value("--link-listen");
+    let link_connect = option_value("--link-connect");
+
+    // Multiplier of the real speed from 0.25, or "unlimited"
+    let speed = option_value("--speed").map_or(Some(1.0), |value| match value.as_str() {
+        "unlimited" => None,
+        value => Some(value.parse::<f64>().expect("Invalid speed").max(0.25))
+    });
+
+    if args.len() > 1 {
+        match args[1].as_str() {
+            "test" => run_test(&args[2..]),
+            "screenshot" => run_screenshot(&args[2..], renderer),
+            "compare" => run_compare(&args[2..], renderer),
+            "play" => run_play(&args[2..]),
+            _ => {}
+        }
+    }
+
+    let controls = Controls::new(&bindings).expect("Cannot load bindings file");
 
     let event_loop = EventLoop::new().unwrap();
     let window = Arc::new({
-        let size = LogicalSize::new(WIDTH, HEIGHT);
-        let scaled_size = LogicalSize::new(WIDTH * 3, HEIGHT * 3);
+        let size = LogicalSize::new(width, height);
+        let scaled_size = LogicalSize::new(width * 3, height * 3);
         WindowBuilder::new()
             .with_title(format!("CRAB-GB [{}]", &args[1]))
             .with_inner_size(scaled_size)
@@ -49,80 +314,141 @@ fn main() {
     let pixels = {
         let window_size = window.inner_size();
         let surface_texture = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(160, 144, surface_texture).unwrap()
+        Pixels::new(width, height, surface_texture).unwrap()
     };
 
-    let mut game = Game::new(pixels);
-    game.cpu.load_rom(read_rom(&args[1]));
+    let mut game = Game::new(pixels, PathBuf::from(&args[1]), controls);
+    game.speed = speed;
 
-    game_loop(event_loop, window, game, 60, 0.5, 
+    // Movies start from power-on with the options they were recorded with
+    let rom = read_rom(&args[1]);
+    let movie = match &play {
+        Some(path) => Movie::load(path).expect("Cannot load movie"),
+        None => {
+            let mut movie = Movie::new(&rom);
+            movie.sgb = sgb;
+            movie.cgb_compatibility = cgb_compatibility;
+            movie.access_blocking = access_blocking;
+            movie.turbo_rate = turbo_rate;
+            movie.allow_opposing = allow_opposing;
+            movie.renderer = renderer;
+            movie
+        }
+    };
+    movie.power_on(&mut game.cpu, rom).expect("Cannot start movie");
+    game.cpu.set_color_correction(color_correction);
+    game.cpu.set_palettes(palettes);
+    if let Some(address) = link_listen {
+        println!("Waiting for the link cable on {}", address);
+        game.cpu.set_link(TcpLink::listen(address.as_str()).expect("Cannot open link cable"));
+    } else if let Some(address) = link_connect {
+        game.cpu.set_link(TcpLink::connect(address.as_str()).expect("Cannot connect link cable"));
+    }
+
+    if play.is_some() {
+        game.movie = Some(MovieState::Playing(movie, 0));
+    } else if let Some(path) = record {
+        game.movie = Some(MovieState::Recording(movie, path));
+    }
+
+    game_loop(event_loop, window, game, 60, 0.5,
         move |g| {
-            g.game.cpu.update();
+            if g.game.paused {
+                // Only the step keys run the emulation
+            } else if g.game.rewinding {
+                g.game.rewind.rewind(&mut g.game.cpu);
+            } else {
+                let elapsed = g.fixed_time_step();
+                g.game.advance(elapsed);
+            }
         }, 
         move |g| {
-            let fb = g.game.cpu.get_framebuffer();
-            let f: &mut [u8] = g.game.pixels.frame_mut();
+            // Only whole frames are shown, except when stepping through one
+            let title = g.game.title();
+            if title != g.game.window_title {
+                g.window.set_title(&title);
+                g.game.window_title = title;
+            }
+
+            let redraw = g.game.cpu.take_new_frame() || g.game.cpu.is_frame_blank() || g.game.paused;
+            if redraw {
+                let f: &mut [u8] = g.game.pixels.frame_mut();
+
+                let screen = if g.game.cpu.is_frame_blank() {
+                    let (r, gr, b) = g.game.cpu.blank_color();
+                    [r, gr, b, 0xFF].repeat(160 * 144)
+                } else {
+                    g.game.cpu.get_framebuffer().to_vec()
+                };
 
-            f.copy_from_slice(&fb);
+                if sgb_border {
+                    // Games without SGB support get a black border
+                    match g.game.cpu.get_sgb_border_frame() {
+                        Some(border) => f.copy_from_slice(&border),
+                        None => f.fill(0)
+                    }
+                    let (x, y) = ((BORDER_WIDTH - 160) / 2, (BORDER_HEIGHT - 144) / 2);
+                    for (line, row) in screen.chunks_exact(160 * 4).enumerate() {
+                        let start = ((y + line) * BORDER_WIDTH + x) * 4;
+                        f[start..start + 160 * 4].copy_from_slice(row);
+                    }
+                } else {
+                    f.copy_from_slice(&screen);
+                }
+            }
 
-            g.game.pixels.render();
+            g.game.pixels.render().unwrap();
 
         }, 
         |g, h| {
             if g.game.input.update(h) {
                 if g.game.input.key_pressed(KeyCode::Escape) || g.game.input.close_requested() {
+                    g.game.save_movie();
                     g.exit();
                     return;
                 }
 
-                if g.game.input.key_held(KeyCode::KeyW) {
-                    g.game.cpu.set_button(cpu::joypad::Button::U);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::U);
-                }
+                // Rewinding, loading states and stepping would make the
+                // movie go out of sync
+                let movie = g.game.movie.is_some();
 
-                if g.game.input.key_held(KeyCode::KeyS) {
-                    g.game.cpu.set_button(cpu::joypad::Button::D);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::D);
-                }
+                g.game.rewinding = g.game.input.key_held(KeyCode::Backspace) && !movie;
+                g.game.fast_forward = g.game.input.key_held(KeyCode::Tab);
 
-                if g.game.input.key_held(KeyCode::KeyA) {
-                    g.game.cpu.set_button(cpu::joypad::Button::L);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::L);
+                for (slot, key) in STATE_SLOT_KEYS.iter().enumerate() {
+                    if g.game.input.key_pressed(*key) {
+                        if g.game.input.held_shift() {
+                            g.game.save_state(slot + 1);
+                        } else if movie {
+                            println!("Cannot load states while a movie is running");
+                        } else {
+                            g.game.load_state(slot + 1);
+                        }
+                    }
                 }
 
-                if g.game.input.key_held(KeyCode::KeyD) {
-                    g.game.cpu.set_button(cpu::joypad::Button::R);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::R);
+                if !g.game.is_playing_movie() {
+                    g.game.controls.update(&g.game.input, &mut g.game.cpu);
                 }
 
-                if g.game.input.key_held(KeyCode::KeyI) {
-                    g.game.cpu.set_button(cpu::joypad::Button::A);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::A);
+                // P pauses, then . runs one frame, , one scanline and / one
+                // instruction
+                if g.game.input.key_pressed(KeyCode::KeyP) {
+                    g.game.paused = !g.game.paused;
+                    println!("{} at frame {}", if g.game.paused { "Paused" } else { "Resumed" }, g.game.cpu.frame_count());
                 }
-
-                if g.game.input.key_held(KeyCode::KeyJ) {
-                    g.game.cpu.set_button(cpu::joypad::Button::B);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::B);
+                if g.game.paused && !movie {
+                    g.game.cpu.set_rendering(true);
+                    if g.game.input.key_pressed(KeyCode::Period) {
+                        g.game.cpu.run_frame();
+                    }
+                    if g.game.input.key_pressed(KeyCode::Comma) {
+                        g.game.cpu.run_scanline();
+                    }
+                    if g.game.input.key_pressed(KeyCode::Slash) {
+                        g.game.cpu.step_instruction();
+                    }
                 }
-
-                if g.game.input.key_held(KeyCode::KeyN) {
-                    g.game.cpu.set_button(cpu::joypad::Button::STA);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::STA);
-                }
-
-                if g.game.input.key_held(KeyCode::KeyB) {
-                    g.game.cpu.set_button(cpu::joypad::Button::SEL);
-                } else {
-                    g.game.cpu.unset_button(cpu::joypad::Button::SEL);
-                }
-
             }
-        });
+        }).unwrap();
 }
@@ -0,0 +1,140 @@
+use std::{fs, io};
+use std::path::Path;
+
+use crate::cpu::{CPU, Renderer};
+use crate::cpu::joypad::DEFAULT_TURBO_RATE;
+
+// Movie file format: magic, version, the ROM header checksum and global
+// checksum, the options that change emulation, then the buttons and turbo
+// inputs held in every frame, one byte each.
+const MAGIC: &[u8; 8] = b"CRABGBMV";
+const VERSION: u16 = 3;
+const HEADER_LENGTH: usize = 15;
+
+fn rom_checksums(rom: &[u8]) -> (u8, u16) {
+    let byte = |address: usize| rom.get(address).copied().unwrap_or(0);
+    (byte(0x14D), u16::from_be_bytes([byte(0x14E), byte(0x14F)]))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// Joypad input from power-on, one entry per frame run with
+// `CPU::run_until_vblank`. Playing it back on a CPU powered on by
+// `power_on` gives the same run as when it was recorded.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Movie {
+    pub sgb: bool,
+    pub cgb_compatibility: bool,
+    pub access_blocking: bool,
+    pub turbo_rate: u8,
+    pub allow_opposing: bool,
+    pub renderer: Renderer,
+    checksums: (u8, u16),
+    // Held buttons and turbo inputs of each frame
+    frames: Vec<(u8, u8)>
+}
+
+impl Movie {
+
+    // An empty movie for `rom`, with the default options.
+    pub fn new(rom: &[u8]) -> Movie {
+        Movie {
+            sgb: false,
+            cgb_compatibility: false,
+            access_blocking: true,
+            turbo_rate: DEFAULT_TURBO_RATE,
+            allow_opposing: false,
+            renderer: Renderer::Scanline,
+            checksums: rom_checksums(rom),
+            frames: Vec::new()
+        }
+    }
+
+    // Loads `rom` into `cpu`, fresh from `CPU::new`, with the options of the
+    // movie, ready for its first frame.
+    pub fn power_on(&self, cpu: &mut CPU, rom: Vec<u8>) -> io::Result<()> {
+        if rom_checksums(&rom) != self.checksums {
+            return Err(invalid_data("Movie was recorded with another ROM"));
+        }
+
+        cpu.set_sgb(self.sgb);
+        cpu.set_cgb_compatibility(self.cgb_compatibility);
+        cpu.load_rom(rom);
+        cpu.set_access_blocking(self.access_blocking);
+        cpu.set_turbo_rate(self.turbo_rate);
+        cpu.set_allow_opposing(self.allow_opposing);
+        cpu.set_renderer(self.renderer);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    // Call before running each frame to record the buttons it sees.
+    pub fn record_frame(&mut self, cpu: &CPU) {
+        self.frames.push((cpu.pressed_buttons(), cpu.turbo_buttons()));
+    }
+
+    // Call before running `frame` to press its buttons. Returns false once
+    // the movie is over.
+    pub fn play_frame(&self, frame: usize, cpu: &mut CPU) -> bool {
+        match self.frames.get(frame) {
+            Some((pressed, turbo)) => {
+                cpu.set_pressed_buttons(*pressed);
+                cpu.set_turbo_buttons(*turbo);
+                true
+            },
+            None => false
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_LENGTH + self.frames.len() * 2);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.push(self.checksums.0);
+        data.extend_from_slice(&self.checksums.1.to_le_bytes());
+        data.push(self.sgb as u8 | (self.cgb_compatibility as u8) << 1 | (self.access_blocking as u8) << 2 | (self.allow_opposing as u8) << 3 | ((self.renderer == Renderer::Fifo) as u8) << 4);
+        data.push(self.turbo_rate);
+        for (pressed, turbo) in &self.frames {
+            data.extend_from_slice(&[*pressed, *turbo]);
+        }
+        data
+    }
+
+    pub fn from_bytes(data: &[u8]) -> io::Result<Movie> {
+        if data.len() < HEADER_LENGTH || !(data.len() - HEADER_LENGTH).is_multiple_of(2) || &data[0..8] != MAGIC {
+            return Err(invalid_data("Not a movie file"));
+        }
+        if u16::from_le_bytes([data[8], data[9]]) != VERSION {
+            return Err(invalid_data("Unsupported movie version"));
+        }
+
+        let options = data[13];
+        Ok(Movie {
+            sgb: options & 1 == 1,
+            cgb_compatibility: (options >> 1) & 1 == 1,
+            access_blocking: (options >> 2) & 1 == 1,
+            turbo_rate: data[14],
+            allow_opposing: (options >> 3) & 1 == 1,
+            renderer: if (options >> 4) & 1 == 1 { Renderer::Fifo } else { Renderer::Scanline },
+            checksums: (data[10], u16::from_le_bytes([data[11], data[12]])),
+            frames: data[HEADER_LENGTH..].chunks_exact(2).map(|frame| (frame[0], frame[1])).collect()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_bytes())
+    }
+
+    pub fn load(path: &Path) -> io::Result<Movie> {
+        Movie::from_bytes(&fs::read(path)?)
+    }
+}
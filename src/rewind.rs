@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use crate::cpu::CPU;
+
+// Save states taken every `interval` frames, keeping at most `capacity` of
+// them. Only the newest is stored in full: each older one is a delta against
+// the one that follows it, which is mostly unchanged bytes.
+pub struct Rewind {
+    interval: u64,
+    capacity: usize,
+    latest: Option<Vec<u8>>,
+    // Oldest first, `deltas[i]` rebuilds snapshot i from snapshot i + 1
+    deltas: VecDeque<Vec<u8>>,
+    next_frame: u64
+}
+
+fn write_length(data: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        data.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    data.push(value as u8);
+}
+
+fn read_length(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[*position];
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Encodes `state` as alternating runs of bytes equal to `base` and bytes
+// copied as they are.
+fn encode_delta(state: &[u8], base: &[u8]) -> Vec<u8> {
+    let same = |i: usize| base.get(i) == Some(&state[i]);
+
+    let mut delta = Vec::new();
+    write_length(&mut delta, state.len());
+    let mut i = 0;
+    while i < state.len() {
+        let start = i;
+        while i < state.len() && same(i) {
+            i += 1;
+        }
+        write_length(&mut delta, i - start);
+
+        let start = i;
+        while i < state.len() && !same(i) {
+            i += 1;
+        }
+        write_length(&mut delta, i - start);
+        delta.extend_from_slice(&state[start..i]);
+    }
+    delta
+}
+
+fn decode_delta(delta: &[u8], base: &[u8]) -> Vec<u8> {
+    let mut position = 0;
+    let length = read_length(delta, &mut position);
+
+    let mut state = Vec::with_capacity(length);
+    while state.len() < length {
+        let same = read_length(delta, &mut position);
+        state.extend_from_slice(&base[state.len()..state.len() + same]);
+
+        let changed = read_length(delta, &mut position);
+        state.extend_from_slice(&delta[position..position + changed]);
+        position += changed;
+    }
+    state
+}
+
+impl Rewind {
+
+    pub fn new(interval: u64, capacity: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            latest: None,
+            deltas: VecDeque::new(),
+            next_frame: 0
+        }
+    }
+
+    // Number of snapshots that can be rewound to.
+    pub fn len(&self) -> usize {
+        self.deltas.len() + self.latest.is_some() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Call once per frame: takes a snapshot when `interval` frames have
+    // passed since the previous one.
+    pub fn record(&mut self, cpu: &CPU) {
+        if cpu.frame_count() < self.next_frame {
+            return;
+        }
+        self.next_frame = cpu.frame_count() + self.interval;
+
+        let state = cpu.save_state();
+        if let Some(previous) = self.latest.replace(state) {
+            let delta = encode_delta(&previous, self.latest.as_ref().unwrap());
+            self.deltas.push_back(delta);
+        }
+        if self.len() > self.capacity {
+            self.deltas.pop_front();
+        }
+    }
+
+    // Restores the newest snapshot and forgets it, so holding rewind keeps
+    // going back in time. Returns false once there is nothing left.
+    pub fn rewind(&mut self, cpu: &mut CPU) -> bool {
+        let Some(state) = self.latest.take() else {
+            return false;
+        };
+        if let Some(delta) = self.deltas.pop_back() {
+            self.latest = Some(decode_delta(&delta, &state));
+        }
+
+        let restored = cpu.load_state(&state).is_ok();
+        self.next_frame = cpu.frame_count() + self.interval;
+        restored
+    }
+}
@@ -0,0 +1,27 @@
+use crab_gb::bindings::Bindings;
+use crab_gb::cpu::joypad::Button;
+
+#[test]
+fn parse_bindings_file() {
+    let bindings = Bindings::parse("
+        ; comment
+        a     = KeyX Enter PadEast
+        b     = KeyZ
+        start =
+        turbo_b = KeyC
+    ").unwrap();
+
+    assert_eq!(bindings.inputs(Button::A), ["KeyX", "Enter", "PadEast"]);
+    assert_eq!(bindings.inputs(Button::B), ["KeyZ"]);
+    assert!(bindings.inputs(Button::STA).is_empty());
+    assert_eq!(bindings.inputs(Button::U), Bindings::new().inputs(Button::U));
+    assert_eq!(bindings.turbo_inputs(Button::B), ["KeyC"]);
+    assert_eq!(bindings.turbo_inputs(Button::A), Bindings::new().turbo_inputs(Button::A));
+}
+
+#[test]
+fn reject_invalid_bindings_file() {
+    assert!(Bindings::parse("jump = Space").is_err());
+    assert!(Bindings::parse("turbo_jump = Space").is_err());
+    assert!(Bindings::parse("KeyX").is_err());
+}
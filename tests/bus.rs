@@ -0,0 +1,86 @@
+use crab_gb::cpu::CPU;
+use crab_gb::cpu::bus::Bus;
+
+// 64 KiB of plain RAM with nothing mapped, recording every tick.
+struct FlatBus {
+    memory: Vec<u8>,
+    ticks: Vec<u8>
+}
+
+impl FlatBus {
+    fn new(program: &[u8]) -> FlatBus {
+        let mut memory = vec![0; 0x10000];
+        memory[..program.len()].copy_from_slice(program);
+        FlatBus { memory, ticks: Vec::new() }
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    fn write(&mut self, address: u16, data: u8) {
+        self.memory[address as usize] = data;
+    }
+
+    fn tick(&mut self, cycles: u8) {
+        self.ticks.push(cycles);
+    }
+}
+
+// Runs from address 0 with every register cleared. The registers end up on
+// the stack, where the test can see them.
+#[test]
+fn cpu_runs_on_flat_bus() {
+    let mut program = vec![
+        0x31, 0x00, 0xD0, // LD SP, 0xD000
+        0x3E, 0x12,       // LD A, 0x12
+        0x06, 0x34,       // LD B, 0x34
+        0x80,             // ADD A, B
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0x22,             // LD (HL+), A
+        0xFE, 0x46,       // CP 0x46
+        0xF5,             // PUSH AF
+        0xE5,             // PUSH HL
+        0xCD, 0x20, 0x00, // CALL 0x0020
+        0x08, 0x02, 0xC0, // LD (0xC002), SP
+    ];
+    program.resize(0x20, 0);
+    program.push(0xC9);   // RET
+
+    let mut cpu = CPU::with_bus(FlatBus::new(&program));
+    let cycles: Vec<u32> = (0..12).map(|_| cpu.step_instruction()).collect();
+
+    let bus = cpu.bus();
+    assert_eq!(bus.ticks, [3, 2, 2, 1, 3, 2, 2, 4, 4, 6, 4, 5]);
+    assert_eq!(cycles, bus.ticks.iter().map(|&tick| tick as u32).collect::<Vec<_>>());
+
+    // A = 0x46 with Z and N set from CP, then HL past the stored A
+    assert_eq!(bus.memory[0xCFFC..0xD000], [0x01, 0xC0, 0xC0, 0x46]);
+    assert_eq!(bus.memory[0xC000], 0x46);
+    // CALL pushed the address after it and RET popped it back into PC and SP
+    assert_eq!(bus.memory[0xCFFA..0xCFFC], [0x13, 0x00]);
+    assert_eq!(bus.memory[0xC002..0xC004], [0xFC, 0xCF]);
+}
+
+// OAM stays readable in the M-cycle after the FF46 write and is blocked from
+// the first byte copied.
+#[test]
+fn oam_dma_setup_cycle_leaves_oam_readable() {
+    let mut cpu = CPU::new();
+    let bus = cpu.bus_mut();
+    bus.write(0xFE00, 0x34);
+    bus.write(0xC000, 0x12);
+
+    bus.write(0xFF46, 0xC0);
+    bus.tick(1);
+    assert_eq!(bus.read(0xFE00), 0x34);
+    bus.tick(1);
+    assert_eq!(bus.read(0xFE00), 0xFF);
+
+    for _ in 0..160 {
+        bus.tick(1);
+    }
+    assert_eq!(bus.read(0xFE00), 0x12);
+}
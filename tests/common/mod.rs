@@ -0,0 +1,183 @@
+#![allow(dead_code)]
+
+use crab_gb::cpu::CPU;
+
+// Builds a 32 KiB ROM-only cartridge whose entry point jumps to `code`,
+// placed right after the header at 0x150.
+pub fn build_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x150..0x150 + code.len()].copy_from_slice(code);
+
+    set_header_checksum(&mut rom);
+    rom
+}
+
+// Keeps scrolling the boot logo, so every frame differs.
+pub fn scroll_rom() -> Vec<u8> {
+    build_rom(&[
+        0x3C,             // loop: INC A
+        0xEA, 0x00, 0xC0, // LD (0xC000), A
+        0xE0, 0x43,       // LDH (SCX), A
+        0x18, 0xF8,       // JR loop
+    ])
+}
+
+// Runs instructions until `frame` frames have been drawn.
+pub fn run_until_frame(cpu: &mut CPU, frame: u64) {
+    while cpu.frame_count() < frame {
+        cpu.step_instruction();
+    }
+}
+
+// Fills the background with a tile whose rows alternate between colour 1 and
+// colour 2, with the identity palette, then runs `main_loop`.
+pub fn stripes_rom_with(main_loop: &[u8]) -> Vec<u8> {
+    let mut code = vec![
+        0xAF,             // XOR A
+        0xE0, 0x40,       // LDH (LCDC), A
+        0xE0, 0x42,       // LDH (SCY), A
+        0xE0, 0x43,       // LDH (SCX), A
+        0x21, 0x00, 0x80, // LD HL, 0x8000
+        0x06, 0x04,       // LD B, 4
+        0x3E, 0xFF,       // tile: LD A, 0xFF
+        0x22,             // LD (HL+), A
+        0xAF,             // XOR A
+        0x22,             // LD (HL+), A
+        0x22,             // LD (HL+), A
+        0x3D,             // DEC A
+        0x22,             // LD (HL+), A
+        0x05,             // DEC B
+        0x20, 0xF5,       // JR NZ, tile
+        0x21, 0x00, 0x98, // LD HL, 0x9800
+        0x01, 0x00, 0x04, // LD BC, 0x0400
+        0xAF,             // map: XOR A
+        0x22,             // LD (HL+), A
+        0x0B,             // DEC BC
+        0x78,             // LD A, B
+        0xB1,             // OR C
+        0x20, 0xF9,       // JR NZ, map
+        0x3E, 0xE4,       // LD A, 0xE4
+        0xE0, 0x47,       // LDH (BGP), A
+        0x3E, 0x91,       // LD A, 0x91
+        0xE0, 0x40,       // LDH (LCDC), A
+    ];
+    code.extend_from_slice(main_loop);
+    build_rom(&code)
+}
+
+// Same as `build_rom`, with the header flagging the game as CGB enhanced.
+pub fn build_cgb_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = build_rom(code);
+    set_cgb_flag(&mut rom);
+    rom
+}
+
+pub fn set_cgb_flag(rom: &mut [u8]) {
+    rom[0x143] = 0x80;
+    set_header_checksum(rom);
+}
+
+// Flags the game as SGB enhanced, which needs the new licensee code.
+pub fn set_sgb_flag(rom: &mut [u8]) {
+    rom[0x146] = 0x03;
+    rom[0x14B] = 0x33;
+    set_header_checksum(rom);
+}
+
+pub const SEND_PACKET: u16 = 0x0200;
+
+// Puts at `SEND_PACKET` a routine sending the 16 byte SGB packet at HL
+// through P1, leaving HL right after it.
+pub fn add_sgb_packet_routine(rom: &mut [u8]) {
+    rom[0x200..0x22B].copy_from_slice(&[
+        0x3E, 0x00, 0xE0, 0x00, // LD A, 0x00; LDH (P1), A (reset)
+        0x3E, 0x30, 0xE0, 0x00, // LD A, 0x30; LDH (P1), A
+        0x06, 0x10,             // LD B, 16
+        0x2A,                   // byte: LD A, (HL+)
+        0x57,                   // LD D, A
+        0x0E, 0x08,             // LD C, 8
+        0xCB, 0x3A,             // bit: SRL D
+        0x3E, 0x10,             // LD A, 0x10
+        0x38, 0x02,             // JR C, send
+        0x3E, 0x20,             // LD A, 0x20
+        0xE0, 0x00,             // send: LDH (P1), A
+        0x3E, 0x30,             // LD A, 0x30
+        0xE0, 0x00,             // LDH (P1), A
+        0x0D,                   // DEC C
+        0x20, 0xEF,             // JR NZ, bit
+        0x05,                   // DEC B
+        0x20, 0xE8,             // JR NZ, byte
+        0x3E, 0x20, 0xE0, 0x00, // LD A, 0x20; LDH (P1), A (stop bit)
+        0x3E, 0x30, 0xE0, 0x00, // LD A, 0x30; LDH (P1), A
+        0xC9,                   // RET
+    ]);
+}
+
+pub fn set_title(rom: &mut [u8], title: &[u8]) {
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    set_header_checksum(rom);
+}
+
+fn set_header_checksum(rom: &mut [u8]) {
+    rom[0x14D] = rom[0x134..0x14D].iter().fold(0u8, |acc, b| acc.wrapping_sub(*b).wrapping_sub(1));
+}
+
+// Copies `length` bytes from `source` to `destination`.
+pub fn copy_code(source: u16, destination: u16, length: u16) -> Vec<u8> {
+    vec![
+        0x21, source as u8, (source >> 8) as u8,           // LD HL, source
+        0x11, destination as u8, (destination >> 8) as u8, // LD DE, destination
+        0x01, length as u8, (length >> 8) as u8,           // LD BC, length
+        0x2A,                                              // loop: LD A, (HL+)
+        0x12,                                              // LD (DE), A
+        0x13,                                              // INC DE
+        0x0B,                                              // DEC BC
+        0x78,                                              // LD A, B
+        0xB1,                                              // OR C
+        0x20, 0xF8,                                        // JR NZ, loop
+    ]
+}
+
+pub const PASS: u16 = 0x0300;
+pub const FAIL: u16 = 0x0310;
+// Prints the zero-terminated string at HL, then loops forever
+pub const PRINT: u16 = 0x0320;
+
+// Like `build_rom`, with routines at `PASS` and `FAIL` that print "Passed" or
+// "Failed" through the serial port, so `code` can simply `JP` to either, and
+// the `PRINT` routine they share.
+pub fn result_rom(code: &[u8]) -> Vec<u8> {
+    let mut rom = build_rom(code);
+    rom[0x300..0x305].copy_from_slice(&[
+        0x21, 0x40, 0x03, // LD HL, 0x0340
+        0x18, 0x1B,       // JR print
+    ]);
+    rom[0x310..0x315].copy_from_slice(&[
+        0x21, 0x50, 0x03, // LD HL, 0x0350
+        0x18, 0x0B,       // JR print
+    ]);
+    rom[0x320..0x32E].copy_from_slice(&[
+        0x2A,             // print: LD A, (HL+)
+        0xB7,             // OR A
+        0x28, 0x08,       // JR Z, done
+        0xE0, 0x01,       // LDH (SB), A
+        0x3E, 0x81,       // LD A, 0x81
+        0xE0, 0x02,       // LDH (SC), A
+        0x18, 0xF4,       // JR print
+        0x18, 0xFE,       // done: JR done
+    ]);
+    rom[0x340..0x347].copy_from_slice(b"Passed\0");
+    rom[0x350..0x357].copy_from_slice(b"Failed\0");
+    rom
+}
+
+// Busy-waits until LY reads `line`.
+pub fn wait_ly(line: u8) -> [u8; 6] {
+    [0xF0, 0x44, 0xFE, line, 0x20, 0xFA] // LDH A, (LY); CP line; JR NZ, -6
+}
+
+// JP Z, PASS; JP FAIL
+pub fn report_z() -> [u8; 6] {
+    [0xCA, PASS as u8, (PASS >> 8) as u8, 0xC3, FAIL as u8, (FAIL >> 8) as u8]
+}
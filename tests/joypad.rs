@@ -0,0 +1,120 @@
+mod common;
+
+use common::build_rom;
+use crab_gb::cpu::bus::Bus;
+use crab_gb::cpu::joypad::Button;
+use crab_gb::cpu::CPU;
+
+const DPAD: u8 = 0x20;
+const BUTTONS: u8 = 0x10;
+
+fn running_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    cpu.load_rom(build_rom(&[0x18, 0xFE])); // JR -2
+    while !cpu.run_until_vblank() {}
+    cpu
+}
+
+// Low nibble of P1 with `row` selected, 0 bits for pressed buttons.
+fn read_row(cpu: &mut CPU, row: u8) -> u8 {
+    cpu.bus_mut().write(0xFF00, row);
+    cpu.bus_mut().read(0xFF00) & 0x0F
+}
+
+#[test]
+fn turbo_presses_every_rate_frames() {
+    let mut cpu = running_cpu();
+    cpu.set_turbo_rate(2);
+    cpu.set_turbo_button(Button::A);
+
+    let mut presses = vec![read_row(&mut cpu, BUTTONS) & 1 == 0];
+    for _ in 0..7 {
+        cpu.run_until_vblank();
+        presses.push(read_row(&mut cpu, BUTTONS) & 1 == 0);
+    }
+    assert_eq!(presses, [true, true, false, false, true, true, false, false]);
+    assert_eq!(cpu.turbo_buttons(), 1 << Button::A as u8);
+
+    // Holding the button itself keeps it pressed
+    cpu.set_button(Button::A);
+    cpu.run_until_vblank();
+    cpu.run_until_vblank();
+    assert_eq!(read_row(&mut cpu, BUTTONS), 0b1110);
+
+    cpu.unset_button(Button::A);
+    cpu.unset_turbo_button(Button::A);
+    assert_eq!(read_row(&mut cpu, BUTTONS), 0b1111);
+}
+
+#[test]
+fn opposing_directions_are_blocked_unless_allowed() {
+    let mut cpu = running_cpu();
+    cpu.set_button(Button::L);
+    cpu.set_button(Button::R);
+    cpu.set_button(Button::U);
+    assert_eq!(read_row(&mut cpu, DPAD), 0b1011);
+
+    cpu.set_allow_opposing(true);
+    assert_eq!(read_row(&mut cpu, DPAD), 0b1000);
+
+    cpu.set_allow_opposing(false);
+    cpu.unset_button(Button::R);
+    assert_eq!(read_row(&mut cpu, DPAD), 0b1001);
+    assert_eq!(cpu.pressed_buttons(), 1 << Button::L as u8 | 1 << Button::U as u8);
+}
+
+#[test]
+fn p1_reads_selected_rows() {
+    let mut cpu = running_cpu();
+    cpu.set_button(Button::A);
+    cpu.set_button(Button::L);
+
+    let mut read = |select: u8| {
+        cpu.bus_mut().write(0xFF00, select);
+        cpu.bus_mut().read(0xFF00)
+    };
+    assert_eq!(read(0x30), 0xFF);
+    assert_eq!(read(DPAD), 0xED);
+    assert_eq!(read(BUTTONS), 0xDE);
+    assert_eq!(read(0x00), 0xCC);
+    assert_eq!(read(0xFF), 0xFF);
+}
+
+fn take_joypad_interrupt(cpu: &mut CPU) -> bool {
+    let flag = cpu.bus_mut().read(0xFF0F);
+    cpu.bus_mut().write(0xFF0F, flag & !0x10);
+    (flag >> 4) & 1 == 1
+}
+
+#[test]
+fn joypad_interrupt_on_falling_edges() {
+    let mut cpu = running_cpu();
+    cpu.bus_mut().write(0xFF00, 0x30);
+    take_joypad_interrupt(&mut cpu);
+
+    // Pressing a button of a row that is not selected
+    cpu.set_button(Button::A);
+    assert!(!take_joypad_interrupt(&mut cpu));
+
+    // Selecting the row pulls the line low
+    cpu.bus_mut().write(0xFF00, BUTTONS);
+    assert!(take_joypad_interrupt(&mut cpu));
+
+    // Selecting both rows keeps P10 low, then pressing Left pulls P11 low
+    cpu.bus_mut().write(0xFF00, 0x00);
+    assert!(!take_joypad_interrupt(&mut cpu));
+    cpu.set_button(Button::L);
+    assert!(take_joypad_interrupt(&mut cpu));
+
+    // Pressing Right on P10, which A already holds low
+    cpu.set_allow_opposing(true);
+    cpu.set_button(Button::R);
+    assert!(!take_joypad_interrupt(&mut cpu));
+    cpu.set_button(Button::D);
+    assert!(take_joypad_interrupt(&mut cpu));
+
+    // Releasing never requests it
+    cpu.unset_button(Button::D);
+    cpu.bus_mut().write(0xFF00, 0x30);
+    assert!(!take_joypad_interrupt(&mut cpu));
+}
@@ -0,0 +1,157 @@
+mod common;
+
+use common::{build_rom, set_title, wait_ly};
+use crab_gb::cpu::{CPU, Renderer};
+use crab_gb::headless;
+use crab_gb::movie::Movie;
+
+const FRAMES: usize = 120;
+
+// Sends the d-pad row of P1 over the serial port once per frame.
+fn input_rom() -> Vec<u8> {
+    let mut code = wait_ly(0x90).to_vec();
+    code.extend_from_slice(&[
+        0x3E, 0x20, 0xE0, 0x00, // LD A, 0x20; LDH (P1), A
+        0xF0, 0x00,             // LDH A, (P1)
+        0xE0, 0x01,             // LDH (SB), A
+        0x3E, 0x81, 0xE0, 0x02, // LD A, 0x81; LDH (SC), A
+    ]);
+    code.extend_from_slice(&wait_ly(0x91));
+    code.extend_from_slice(&[0x18, 0xE6]); // JR loop
+    build_rom(&code)
+}
+
+// Puts ten sprites on line 0x40, then times its mode 3 by polling STAT and
+// sends the count over the serial port once per frame.
+fn stat_rom() -> Vec<u8> {
+    let mut code = wait_ly(0x90).to_vec();
+    code.extend_from_slice(&[
+        0x21, 0x00, 0xFE,       // LD HL, OAM
+        0x06, 0x0A,             // LD B, 10
+        0x3E, 0x50, 0x22,       // sprite: LD A, 0x50; LD (HL+), A
+        0x3E, 0x20, 0x22,       // LD A, 0x20; LD (HL+), A
+        0xAF, 0x22, 0x22,       // XOR A; LD (HL+), A; LD (HL+), A
+        0x05,                   // DEC B
+        0x20, 0xF4,             // JR NZ, sprite
+        0x3E, 0x93, 0xE0, 0x40, // LD A, 0x93; LDH (LCDC), A
+    ]);
+    code.extend_from_slice(&wait_ly(0x40)); // loop
+    code.extend_from_slice(&[
+        0xF0, 0x41, 0xE6, 0x03, // mode3: LDH A, (STAT); AND 3
+        0xFE, 0x03, 0x20, 0xF8, // CP 3; JR NZ, mode3
+        0x06, 0x00,             // LD B, 0
+        0x04,                   // count: INC B
+        0xF0, 0x41, 0xE6, 0x03, // LDH A, (STAT); AND 3
+        0x20, 0xF9,             // JR NZ, count
+        0x78, 0xE0, 0x01,       // LD A, B; LDH (SB), A
+        0x3E, 0x81, 0xE0, 0x02, // LD A, 0x81; LDH (SC), A
+    ]);
+    code.extend_from_slice(&wait_ly(0x41));
+    code.extend_from_slice(&[0x18, 0xDA]); // JR loop
+    build_rom(&code)
+}
+
+// Holds right, then up, then nothing, a few frames each.
+fn record(movie: &mut Movie, cpu: &mut CPU) {
+    for frame in 0..FRAMES {
+        cpu.set_pressed_buttons([0b0001, 0b0100, 0b0000][frame / 7 % 3]);
+        movie.record_frame(cpu);
+        cpu.run_until_vblank();
+    }
+}
+
+#[test]
+fn movie_plays_back_recorded_run() {
+    let mut movie = Movie::new(&input_rom());
+    let mut recorded = CPU::new();
+    movie.power_on(&mut recorded, input_rom()).unwrap();
+    recorded.set_serial_capture(true);
+    record(&mut movie, &mut recorded);
+    assert_eq!(movie.len(), FRAMES);
+
+    let mut cpu = CPU::new();
+    movie.power_on(&mut cpu, input_rom()).unwrap();
+    cpu.set_serial_capture(true);
+    let mut frame = 0;
+    while movie.play_frame(frame, &mut cpu) {
+        cpu.run_until_vblank();
+        frame += 1;
+    }
+    assert_eq!(frame, FRAMES);
+
+    let serial = recorded.bus().serial_output();
+    for dpad in [0x0E, 0x0B, 0x0F] {
+        assert!(serial.iter().any(|byte| byte & 0x0F == dpad));
+    }
+    assert_eq!(cpu.bus().serial_output(), serial);
+    assert_eq!(cpu.get_framebuffer(), recorded.get_framebuffer());
+    assert_eq!(cpu.save_state(), recorded.save_state());
+}
+
+// Serial output and final state of playing `movie` back.
+fn play(movie: &Movie, rom: Vec<u8>) -> (Vec<u8>, Vec<u8>) {
+    let mut cpu = CPU::new();
+    movie.power_on(&mut cpu, rom).unwrap();
+    cpu.set_serial_capture(true);
+    let mut frame = 0;
+    while movie.play_frame(frame, &mut cpu) {
+        cpu.run_until_vblank();
+        frame += 1;
+    }
+    (cpu.bus().serial_output().to_vec(), cpu.save_state())
+}
+
+#[test]
+fn movie_plays_back_with_recorded_renderer() {
+    let mut movie = Movie::new(&stat_rom());
+    movie.renderer = Renderer::Fifo;
+    let mut recorded = CPU::new();
+    movie.power_on(&mut recorded, stat_rom()).unwrap();
+    recorded.set_serial_capture(true);
+    record(&mut movie, &mut recorded);
+
+    let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    assert_eq!(loaded.renderer, Renderer::Fifo);
+    let (serial, state) = play(&loaded, stat_rom());
+    assert_eq!(serial, recorded.bus().serial_output());
+    assert_eq!(state, recorded.save_state());
+
+    // Mode 3 lasts longer with the sprites on the FIFO renderer only
+    let mut scanline = loaded.clone();
+    scanline.renderer = Renderer::Scanline;
+    assert_ne!(play(&scanline, stat_rom()).0, serial);
+}
+
+fn recorded_screenshot(movie: &mut Movie) -> u64 {
+    let mut cpu = CPU::new();
+    movie.power_on(&mut cpu, input_rom()).unwrap();
+    record(movie, &mut cpu);
+    headless::framebuffer_hash(&cpu.get_framebuffer())
+}
+
+#[test]
+fn movie_plays_back_headlessly() {
+    let mut movie = Movie::new(&input_rom());
+    let hash = recorded_screenshot(&mut movie);
+
+    let framebuffer = headless::run_movie(input_rom(), &movie).unwrap();
+    assert_eq!(headless::framebuffer_hash(&framebuffer), hash);
+}
+
+#[test]
+fn movie_file_round_trip() {
+    let mut movie = Movie::new(&input_rom());
+    let mut cpu = CPU::new();
+    movie.power_on(&mut cpu, input_rom()).unwrap();
+    record(&mut movie, &mut cpu);
+    movie.sgb = true;
+    movie.access_blocking = false;
+
+    let loaded = Movie::from_bytes(&movie.to_bytes()).unwrap();
+    assert_eq!(loaded, movie);
+
+    assert!(Movie::from_bytes(b"CRABGBSS").is_err());
+    let mut other_rom = input_rom();
+    set_title(&mut other_rom, b"OTHER");
+    assert!(movie.power_on(&mut CPU::new(), other_rom).is_err());
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{build_rom, run_until_frame, scroll_rom, stripes_rom_with};
+use crab_gb::cpu::bus::Bus;
+use crab_gb::cpu::{CPU, DOTS_PER_FRAME};
+
+#[test]
+fn run_frame_runs_one_frame() {
+    assert_eq!(DOTS_PER_FRAME, 154 * 456);
+
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    for _ in 0..10 {
+        cpu.run_frame();
+    }
+
+    let start = cpu.frame_count();
+    for _ in 0..600 {
+        cpu.run_frame();
+    }
+    assert_eq!(cpu.frame_count() - start, 600);
+}
+
+#[test]
+fn skipped_frames_keep_emulating() {
+    let mut drawn = CPU::new();
+    drawn.load_rom(scroll_rom());
+    let mut skipped = CPU::new();
+    skipped.load_rom(scroll_rom());
+    skipped.set_rendering(false);
+
+    for _ in 0..120 {
+        drawn.run_frame();
+        skipped.run_frame();
+    }
+    assert_eq!(skipped.frame_count(), drawn.frame_count());
+    assert!(skipped.get_framebuffer().iter().all(|&byte| byte == 0xFF));
+
+    // The next drawn frame is the same on both
+    skipped.set_rendering(true);
+    drawn.run_frame();
+    skipped.run_frame();
+    assert_eq!(skipped.get_framebuffer(), drawn.get_framebuffer());
+}
+
+// Covers the whole screen with the window, striped like the background.
+fn window_rom() -> Vec<u8> {
+    stripes_rom_with(&[
+        0xAF,       // XOR A
+        0xE0, 0x4A, // LDH (WY), A
+        0x3E, 0x07, // LD A, 7
+        0xE0, 0x4B, // LDH (WX), A
+        0x3E, 0xB1, // LD A, 0xB1
+        0xE0, 0x40, // LDH (LCDC), A
+        0x18, 0xFE, // JR -2
+    ])
+}
+
+// Runs `cpu` past `frame` frames, until LY reads `line`.
+fn run_until_line(cpu: &mut CPU, frame: u64, line: u8) {
+    run_until_frame(cpu, frame);
+    while cpu.bus_mut().read(0xFF44) != line {
+        cpu.step_instruction();
+    }
+}
+
+// Skipping the top of a frame still counts the window lines, so the rest of
+// it is drawn as without skipping.
+#[test]
+fn skipped_lines_keep_window_line() {
+    let mut drawn = CPU::new();
+    drawn.load_rom(window_rom());
+    run_until_line(&mut drawn, 150, 73);
+
+    let mut skipped = CPU::new();
+    skipped.load_rom(window_rom());
+    run_until_frame(&mut skipped, 150);
+    skipped.set_rendering(false);
+    run_until_line(&mut skipped, 150, 73);
+    skipped.set_rendering(true);
+    assert_eq!(skipped.save_state(), drawn.save_state());
+
+    run_until_frame(&mut drawn, 152);
+    run_until_frame(&mut skipped, 152);
+    assert_eq!(skipped.get_framebuffer(), drawn.get_framebuffer());
+}
+
+#[test]
+fn scanlines_add_up_to_frames() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    for _ in 0..10 {
+        cpu.run_frame();
+    }
+
+    let start = cpu.frame_count();
+    for _ in 0..154 * 30 {
+        cpu.run_scanline();
+    }
+    assert_eq!(cpu.frame_count() - start, 30);
+}
+
+#[test]
+fn run_until_vblank_stops_at_mode_1() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    assert!(!cpu.take_new_frame());
+    // The LCD is off until the boot ROM turns it on
+    while !cpu.run_until_vblank() {}
+
+    let start = cpu.frame_count();
+    for frame in 1..=100 {
+        assert!(cpu.run_until_vblank());
+        assert_eq!(cpu.frame_count(), start + frame);
+        assert_eq!(cpu.bus_mut().read(0xFF41) & 0b11, 0b01);
+        assert_eq!(cpu.bus_mut().read(0xFF44), 144);
+    }
+    assert!(cpu.take_new_frame());
+    assert!(!cpu.take_new_frame());
+
+    cpu.run_scanline();
+    assert!(!cpu.take_new_frame());
+}
+
+#[test]
+fn run_until_vblank_with_lcd_off() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(build_rom(&[
+        0xAF,             // XOR A
+        0xE0, 0x40,       // LDH (LCDC), A
+        0x18, 0xFE,       // JR -2
+    ]));
+    while cpu.bus_mut().read(0xFF40) != 0 {
+        cpu.step_instruction();
+    }
+
+    let frame = cpu.frame_count();
+    assert!(!cpu.run_until_vblank());
+    assert_eq!(cpu.frame_count(), frame);
+}
@@ -0,0 +1,23 @@
+use crab_gb::cpu::{Palette, Palettes};
+
+#[test]
+fn parse_palette_file() {
+    let palettes = Palettes::parse("
+        ; comment
+        all  = pocket
+        bg   = green
+        obp1 = #FFFFFF FF8484 #943A3A 000000
+    ").unwrap();
+
+    assert_eq!(palettes.bg, Palette::CLASSIC_GREEN);
+    assert_eq!(palettes.obp0, Palette::POCKET);
+    assert_eq!(palettes.obp1, Palette([(0xFF, 0xFF, 0xFF), (0xFF, 0x84, 0x84), (0x94, 0x3A, 0x3A), (0x00, 0x00, 0x00)]));
+}
+
+#[test]
+fn reject_invalid_palette_file() {
+    assert!(Palettes::parse("bg = FFFFFF FFFFFF FFFFFF").is_err());
+    assert!(Palettes::parse("bg = FFFFFF FFFFFF FFFFFF GGGGGG").is_err());
+    assert!(Palettes::parse("window = green").is_err());
+    assert!(Palettes::parse("green").is_err());
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use common::{run_until_frame, scroll_rom};
+use crab_gb::cpu::CPU;
+use crab_gb::rewind::Rewind;
+
+// Runs `frames` frames recording into `rewind`, returning the state after
+// each of them.
+fn record_frames(cpu: &mut CPU, rewind: &mut Rewind, frames: u64) -> Vec<Vec<u8>> {
+    (1..=frames).map(|frame| {
+        run_until_frame(cpu, frame);
+        rewind.record(cpu);
+        cpu.save_state()
+    }).collect()
+}
+
+#[test]
+fn rewind_steps_back_through_snapshots() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    let mut rewind = Rewind::new(10, 100);
+    let states = record_frames(&mut cpu, &mut rewind, 60);
+
+    // Snapshots of frames 1, 11, ..., 51
+    assert_eq!(rewind.len(), 6);
+    for frame in [51, 41, 31, 21, 11, 1] {
+        assert!(rewind.rewind(&mut cpu));
+        assert_eq!(cpu.save_state(), states[frame - 1]);
+    }
+    assert!(!rewind.rewind(&mut cpu));
+    assert!(rewind.is_empty());
+}
+
+#[test]
+fn rewind_keeps_newest_snapshots() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    let mut rewind = Rewind::new(5, 3);
+    let states = record_frames(&mut cpu, &mut rewind, 30);
+
+    assert_eq!(rewind.len(), 3);
+    for frame in [26, 21, 16] {
+        assert!(rewind.rewind(&mut cpu));
+        assert_eq!(cpu.save_state(), states[frame - 1]);
+    }
+    assert!(!rewind.rewind(&mut cpu));
+}
+
+#[test]
+fn record_after_rewind() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    let mut rewind = Rewind::new(10, 100);
+    record_frames(&mut cpu, &mut rewind, 40);
+
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.frame_count(), 31);
+    let states = record_frames(&mut cpu, &mut rewind, 50);
+
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.save_state(), states[40]);
+    assert!(rewind.rewind(&mut cpu));
+    assert_eq!(cpu.frame_count(), 21);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::io::ErrorKind;
+
+use common::{run_until_frame, scroll_rom, set_title};
+use crab_gb::cpu::CPU;
+
+#[test]
+fn save_state_round_trip() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    run_until_frame(&mut cpu, 30);
+    let state = cpu.save_state();
+    run_until_frame(&mut cpu, 60);
+
+    let mut restored = CPU::new();
+    restored.load_rom(scroll_rom());
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    run_until_frame(&mut restored, 60);
+
+    assert_eq!(restored.save_state(), cpu.save_state());
+    assert_eq!(restored.get_framebuffer(), cpu.get_framebuffer());
+}
+
+// `run_frame` carries the dots the last instruction ran over into the next
+// call, so a state loaded between lines keeps the same pace.
+#[test]
+fn load_state_mid_frame_keeps_pace() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    for _ in 0..30 {
+        cpu.run_frame();
+    }
+    for _ in 0..70 {
+        cpu.run_scanline();
+    }
+    let state = cpu.save_state();
+    for _ in 0..30 {
+        cpu.run_frame();
+    }
+
+    let mut restored = CPU::new();
+    restored.load_rom(scroll_rom());
+    restored.load_state(&state).unwrap();
+    for _ in 0..30 {
+        restored.run_frame();
+    }
+
+    assert_eq!(restored.save_state(), cpu.save_state());
+    assert_eq!(restored.get_framebuffer(), cpu.get_framebuffer());
+}
+
+#[test]
+fn reject_state_of_other_rom() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    run_until_frame(&mut cpu, 10);
+    let state = cpu.save_state();
+
+    let mut rom = scroll_rom();
+    set_title(&mut rom, b"OTHER");
+    let mut other = CPU::new();
+    other.load_rom(rom);
+    let before = other.save_state();
+
+    assert_eq!(other.load_state(&state).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(other.save_state(), before);
+}
+
+#[test]
+fn reject_invalid_state() {
+    let mut cpu = CPU::new();
+    cpu.load_rom(scroll_rom());
+    run_until_frame(&mut cpu, 10);
+    let state = cpu.save_state();
+    let before = cpu.save_state();
+
+    assert_eq!(cpu.load_state(&state[..state.len() - 1]).unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    assert_eq!(cpu.load_state(b"not a save state").unwrap_err().kind(), ErrorKind::InvalidData);
+
+    let mut newer = state.clone();
+    newer[8] = 0xFF;
+    assert_eq!(cpu.load_state(&newer).unwrap_err().kind(), ErrorKind::InvalidData);
+    assert_eq!(cpu.save_state(), before);
+}
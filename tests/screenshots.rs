@@ -0,0 +1,430 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{build_cgb_rom, build_rom, copy_code, stripes_rom_with, wait_ly};
+use crab_gb::cpu::{CPU, Renderer};
+use crab_gb::headless::{
+    compare_screenshot, framebuffer_hash, run_frames, save_png, Reference, ScreenshotResult, SCREEN_HEIGHT, SCREEN_WIDTH
+};
+
+const FRAMES: u64 = 120;
+
+fn stripes_rom() -> Vec<u8> {
+    stripes_rom_with(&[
+        0x18, 0xFE,       // JR -2
+    ])
+}
+
+// Keeps swapping BGP between the identity palette and its inverse.
+fn palette_swap_rom() -> Vec<u8> {
+    stripes_rom_with(&[
+        0x3E, 0x1B,       // loop: LD A, 0x1B
+        0xE0, 0x47,       // LDH (BGP), A
+        0x3E, 0xE4,       // LD A, 0xE4
+        0xE0, 0x47,       // LDH (BGP), A
+        0x18, 0xF6,       // JR loop
+    ])
+}
+
+// Tiles 0-5: blank, colour 3, colour 1, colour 2, colour 1, colour 3.
+fn sprite_tiles() -> Vec<u8> {
+    let rows: [[u8; 2]; 6] = [[0x00, 0x00], [0xFF, 0xFF], [0xFF, 0x00], [0x00, 0xFF], [0xFF, 0x00], [0xFF, 0xFF]];
+    rows.iter().flat_map(|row| row.repeat(8)).collect()
+}
+
+// Loads `tiles` and `oam`, clears the background to tile 0 except for tiles
+// (0, 4) and (1, 4), which use tile 3, and turns the LCD on with `lcdc`.
+fn sprites_rom(oam: &[(u8, u8, u8, u8)], lcdc: u8) -> Vec<u8> {
+    let mut code = vec![
+        0xAF,             // XOR A
+        0xE0, 0x40,       // LDH (LCDC), A
+        0xE0, 0x42,       // LDH (SCY), A
+        0xE0, 0x43,       // LDH (SCX), A
+    ];
+    code.extend(copy_code(0x1000, 0x8000, 0x60));
+    code.extend(copy_code(0x4000, 0x9800, 0x400));
+    code.extend(copy_code(0x1100, 0xFE00, 0xA0));
+    code.extend([
+        0x3E, 0x03,       // LD A, 3
+        0xEA, 0x80, 0x98, // LD (0x9880), A
+        0xEA, 0x81, 0x98, // LD (0x9881), A
+        0x3E, 0xE4,       // LD A, 0xE4
+        0xE0, 0x47,       // LDH (BGP), A
+        0xE0, 0x48,       // LDH (OBP0), A
+        0x3E, lcdc,       // LD A, lcdc
+        0xE0, 0x40,       // LDH (LCDC), A
+        0x18, 0xFE,       // JR -2
+    ]);
+
+    let mut rom = build_rom(&code);
+    rom[0x1000..0x1060].copy_from_slice(&sprite_tiles());
+    for (i, (y, x, tile, attributes)) in oam.iter().enumerate() {
+        rom[0x1100 + i * 4..0x1104 + i * 4].copy_from_slice(&[*y, *x, *tile, *attributes]);
+    }
+    rom
+}
+
+// Shades (0 = white, 3 = black) of one line of the framebuffer.
+fn line_shades(framebuffer: &[u8], y: usize) -> Vec<u8> {
+    framebuffer[y * SCREEN_WIDTH * 4..(y + 1) * SCREEN_WIDTH * 4].chunks_exact(4).map(|p| match p[0] {
+        0xFF => 0,
+        0xCC => 1,
+        0x77 => 2,
+        0x00 => 3,
+        x => panic!("Unexpected shade: {:#04x}", x)
+    }).collect()
+}
+
+// Builds a line from (first x, last x, shade) spans over a white background.
+fn expected_line(spans: &[(usize, usize, u8)]) -> Vec<u8> {
+    let mut line = vec![0; SCREEN_WIDTH];
+    for (first, last, shade) in spans {
+        line[*first..=*last].fill(*shade);
+    }
+    line
+}
+
+fn check_sprites(renderer: Renderer) {
+    let mut oam: Vec<(u8, u8, u8, u8)> = (0..12).map(|i| (16, 8 + i * 8, 1, 0)).collect();
+    oam.extend([
+        (32, 24, 2, 0),
+        (32, 20, 1, 0),
+        (48, 12, 1, 0x80),
+        (48, 28, 1, 0x80),
+    ]);
+    let framebuffer = run_frames(sprites_rom(&oam, 0x93), FRAMES, renderer);
+
+    // Only the first 10 objects on a line are drawn
+    assert_eq!(line_shades(&framebuffer, 0), expected_line(&[(0, 79, 3)]));
+    // The object with the smaller X wins even if it comes later in OAM
+    assert_eq!(line_shades(&framebuffer, 16), expected_line(&[(12, 19, 3), (20, 23, 1)]));
+    // BG-over-OBJ objects only show through BG colour 0
+    assert_eq!(line_shades(&framebuffer, 32), expected_line(&[(0, 15, 2), (20, 27, 3)]));
+}
+
+fn check_tall_sprites(renderer: Renderer) {
+    let oam = [(16, 8, 5, 0), (16, 24, 4, 0x40)];
+    let framebuffer = run_frames(sprites_rom(&oam, 0x97), FRAMES, renderer);
+
+    for y in 0..8 {
+        assert_eq!(line_shades(&framebuffer, y), expected_line(&[(0, 7, 1), (16, 23, 3)]));
+    }
+    for y in 8..16 {
+        assert_eq!(line_shades(&framebuffer, y), expected_line(&[(0, 7, 3), (16, 23, 1)]));
+    }
+}
+
+#[test]
+fn sprites() {
+    check_sprites(Renderer::Scanline);
+}
+
+#[test]
+fn sprites_fifo() {
+    check_sprites(Renderer::Fifo);
+}
+
+#[test]
+fn tall_sprites() {
+    check_tall_sprites(Renderer::Scanline);
+}
+
+#[test]
+fn tall_sprites_fifo() {
+    check_tall_sprites(Renderer::Fifo);
+}
+
+// Shows the window from line 8 with the window map rows alternating between
+// tile 1 (black) and tile 0 (white) over a white background, then runs
+// `main_loop`.
+fn window_rom(wx: u8, main_loop: &[u8]) -> Vec<u8> {
+    let mut code = vec![
+        0xAF,             // XOR A
+        0xE0, 0x40,       // LDH (LCDC), A
+        0xE0, 0x42,       // LDH (SCY), A
+        0xE0, 0x43,       // LDH (SCX), A
+    ];
+    code.extend(copy_code(0x1000, 0x8000, 0x60));
+    code.extend(copy_code(0x4000, 0x9800, 0x400));
+    code.extend(copy_code(0x1200, 0x9C00, 0x400));
+    code.extend([
+        0x3E, 0x08,       // LD A, 8
+        0xE0, 0x4A,       // LDH (WY), A
+        0x3E, wx,         // LD A, wx
+        0xE0, 0x4B,       // LDH (WX), A
+        0x3E, 0xE4,       // LD A, 0xE4
+        0xE0, 0x47,       // LDH (BGP), A
+        0x3E, 0xF1,       // LD A, 0xF1
+        0xE0, 0x40,       // LDH (LCDC), A
+    ]);
+    code.extend_from_slice(main_loop);
+
+    let mut rom = build_rom(&code);
+    rom[0x1000..0x1060].copy_from_slice(&sprite_tiles());
+    for row in (0..32).step_by(2) {
+        rom[0x1200 + row * 32..0x1200 + row * 32 + 32].fill(1);
+    }
+    rom
+}
+
+fn check_window_line_counter(renderer: Renderer) {
+    // Moves the window off screen for lines 40 to 59.
+    let rom = window_rom(47, &[
+        0xF0, 0x44,       // loop: LDH A, (LY)
+        0xFE, 0x28,       // CP 40
+        0x20, 0xFA,       // JR NZ, loop
+        0x3E, 0xC8,       // LD A, 200
+        0xE0, 0x4B,       // LDH (WX), A
+        0xF0, 0x44,       // wait: LDH A, (LY)
+        0xFE, 0x3C,       // CP 60
+        0x20, 0xFA,       // JR NZ, wait
+        0x3E, 0x2F,       // LD A, 47
+        0xE0, 0x4B,       // LDH (WX), A
+        0x18, 0xEA,       // JR loop
+    ]);
+    let framebuffer = run_frames(rom, FRAMES, renderer);
+
+    for y in 0..SCREEN_HEIGHT {
+        let window_line = match y {
+            0..=7 | 40..=59 => None,
+            8..=39 => Some(y - 8),
+            _ => Some(y - 60 + 32)
+        };
+        let expected = match window_line {
+            Some(line) if (line / 8) % 2 == 0 => expected_line(&[(40, 159, 3)]),
+            _ => expected_line(&[])
+        };
+        assert_eq!(line_shades(&framebuffer, y), expected, "line {}", y);
+    }
+}
+
+fn check_window_wx_166(renderer: Renderer) {
+    let framebuffer = run_frames(window_rom(166, &[0x18, 0xFE]), FRAMES, renderer);
+
+    assert_eq!(line_shades(&framebuffer, 7), expected_line(&[]));
+    assert_eq!(line_shades(&framebuffer, 8), expected_line(&[(159, 159, 3)]));
+    for y in 9..SCREEN_HEIGHT {
+        let expected = if ((y - 8) / 8) % 2 == 0 { expected_line(&[(0, 159, 3)]) } else { expected_line(&[]) };
+        assert_eq!(line_shades(&framebuffer, y), expected, "line {}", y);
+    }
+}
+
+#[test]
+fn window_line_counter() {
+    check_window_line_counter(Renderer::Scanline);
+}
+
+#[test]
+fn window_line_counter_fifo() {
+    check_window_line_counter(Renderer::Fifo);
+}
+
+#[test]
+fn window_wx_166() {
+    check_window_wx_166(Renderer::Scanline);
+}
+
+#[test]
+fn window_wx_166_fifo() {
+    check_window_wx_166(Renderer::Fifo);
+}
+
+// CGB mode: red background from BG palette 0, except for the first tile
+// which uses palette 1 (blue) and the second which takes its data from VRAM
+// bank 1 (colour 1, green).
+fn cgb_rom() -> Vec<u8> {
+    let mut code = wait_ly(0x90).to_vec();
+    code.extend_from_slice(&[
+        0x3E, 0x80, 0xE0, 0x68, // BCPS = palette 0, auto increment
+        0x3E, 0x1F, 0xE0, 0x69, // colour 0 = 0x001F
+        0xAF, 0xE0, 0x69,
+        0x3E, 0xE0, 0xE0, 0x69, // colour 1 = 0x03E0
+        0x3E, 0x03, 0xE0, 0x69,
+        0x3E, 0x88, 0xE0, 0x68, // BCPS = palette 1, auto increment
+        0xAF, 0xE0, 0x69,       // colour 0 = 0x7C00
+        0x3E, 0x7C, 0xE0, 0x69,
+        0x3E, 0x01, 0xE0, 0x4F, // VBK = 1
+        0x3E, 0x01,             // LD A, 0x01
+        0xEA, 0x00, 0x98,       // LD (0x9800), A
+        0x3E, 0x08,             // LD A, 0x08
+        0xEA, 0x01, 0x98,       // LD (0x9801), A
+        0x21, 0x10, 0x80,       // LD HL, 0x8010
+        0x06, 0x08,             // LD B, 8
+        0x3E, 0xFF,             // tile: LD A, 0xFF
+        0x22,                   // LD (HL+), A
+        0xAF,                   // XOR A
+        0x22,                   // LD (HL+), A
+        0x05,                   // DEC B
+        0x20, 0xF8,             // JR NZ, tile
+        0xAF, 0xE0, 0x4F,       // VBK = 0
+        0x3E, 0x01,             // LD A, 0x01
+        0xEA, 0x01, 0x98,       // LD (0x9801), A
+        0x18, 0xFE,             // JR -2
+    ]);
+    build_cgb_rom(&code)
+}
+
+fn check_cgb(renderer: Renderer) {
+    let framebuffer = run_frames(cgb_rom(), FRAMES, renderer);
+
+    let pixel = |x: usize, y: usize| &framebuffer[(y * SCREEN_WIDTH + x) * 4..(y * SCREEN_WIDTH + x) * 4 + 3];
+    for y in [0, 7] {
+        assert_eq!(pixel(0, y), [0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(7, y), [0x00, 0x00, 0xFF]);
+        assert_eq!(pixel(8, y), [0x00, 0xFF, 0x00]);
+        assert_eq!(pixel(15, y), [0x00, 0xFF, 0x00]);
+        assert_eq!(pixel(16, y), [0xFF, 0x00, 0x00]);
+    }
+    assert_eq!(pixel(0, 8), [0xFF, 0x00, 0x00]);
+    assert_eq!(pixel(159, 143), [0xFF, 0x00, 0x00]);
+}
+
+#[test]
+fn cgb_palettes_and_attributes() {
+    check_cgb(Renderer::Scanline);
+}
+
+#[test]
+fn cgb_palettes_and_attributes_fifo() {
+    check_cgb(Renderer::Fifo);
+}
+
+// First color of the BG palette the boot ROM picks for a DMG game on CGB
+// hardware, with the stripes on screen.
+fn compatibility_bg_color(title: &[u8], licensee: u8) -> [u8; 4] {
+    let mut rom = stripes_rom();
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    rom[0x14B] = licensee;
+
+    let mut cpu = CPU::new();
+    cpu.set_cgb_compatibility(true);
+    cpu.load_rom(rom);
+    while cpu.frame_count() < FRAMES {
+        cpu.step_instruction();
+    }
+
+    let framebuffer = cpu.get_framebuffer();
+    assert_eq!(framebuffer[0..4], framebuffer[(2 * SCREEN_WIDTH + 40) * 4..(2 * SCREEN_WIDTH + 40) * 4 + 4]);
+    framebuffer[0..4].try_into().unwrap()
+}
+
+// DMG games on CGB hardware get the palettes the boot ROM picks from the
+// title checksum, and from its 4th letter for checksums shared by several
+// games.
+#[test]
+fn cgb_compatibility_palettes() {
+    assert_eq!(compatibility_bg_color(b"POKEMON RED", 0x01), [0xFF, 0x84, 0x84, 0xFF]);
+    assert_eq!(compatibility_bg_color(b"TETRIS", 0x01), [0xFF, 0xFF, 0x00, 0xFF]);
+    assert_eq!(compatibility_bg_color(b"SUPER MARIOLAND", 0x01), [0xFF, 0xFF, 0x94, 0xFF]);
+    assert_eq!(compatibility_bg_color(b"METROID2", 0x01), [0x63, 0xA5, 0xFF, 0xFF]);
+    // Not published by Nintendo
+    assert_eq!(compatibility_bg_color(b"POKEMON RED", 0x00), [0x7B, 0xFF, 0x31, 0xFF]);
+}
+
+fn stripes_image(even: u8, odd: u8) -> Vec<u8> {
+    (0..SCREEN_WIDTH * SCREEN_HEIGHT).flat_map(|i| {
+        let grey = if (i / SCREEN_WIDTH).is_multiple_of(2) { even } else { odd };
+        [grey, grey, grey, 0xFF]
+    }).collect()
+}
+
+fn output_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR")).join(name)
+}
+
+#[test]
+fn stripes_match_png() {
+    let reference = output_path("stripes.png");
+    save_png(&reference, SCREEN_WIDTH, SCREEN_HEIGHT, &stripes_image(0xAA, 0x55)).unwrap();
+
+    let framebuffer = run_frames(stripes_rom(), FRAMES, Renderer::Scanline);
+    let result = compare_screenshot(&framebuffer, &Reference::Png(reference), None).unwrap();
+    assert_eq!(result, ScreenshotResult::Match);
+}
+
+#[test]
+fn stripes_mismatch_writes_diff() {
+    let reference = output_path("stripes-inverted.png");
+    let diff = output_path("stripes-inverted-diff.png");
+    save_png(&reference, SCREEN_WIDTH, SCREEN_HEIGHT, &stripes_image(0x55, 0xAA)).unwrap();
+    let _ = std::fs::remove_file(&diff);
+
+    let framebuffer = run_frames(stripes_rom(), FRAMES, Renderer::Scanline);
+    let result = compare_screenshot(&framebuffer, &Reference::Png(reference), Some(&diff)).unwrap();
+    assert_eq!(result, ScreenshotResult::Mismatch { pixels: SCREEN_WIDTH * SCREEN_HEIGHT });
+    assert!(diff.exists());
+}
+
+#[test]
+fn stripes_match_png_fifo() {
+    let reference = output_path("stripes-fifo.png");
+    save_png(&reference, SCREEN_WIDTH, SCREEN_HEIGHT, &stripes_image(0xAA, 0x55)).unwrap();
+
+    let framebuffer = run_frames(stripes_rom(), FRAMES, Renderer::Fifo);
+    let result = compare_screenshot(&framebuffer, &Reference::Png(reference), None).unwrap();
+    assert_eq!(result, ScreenshotResult::Match);
+}
+
+#[test]
+fn fifo_shows_mid_line_palette_writes() {
+    let lines_with_changes = |framebuffer: &[u8]| {
+        framebuffer.chunks_exact(SCREEN_WIDTH * 4)
+            .filter(|line| line.chunks_exact(4).any(|p| p != &line[..4]))
+            .count()
+    };
+
+    let scanline = run_frames(palette_swap_rom(), FRAMES, Renderer::Scanline);
+    assert_eq!(lines_with_changes(&scanline), 0);
+
+    let fifo = run_frames(palette_swap_rom(), FRAMES, Renderer::Fifo);
+    assert!(lines_with_changes(&fifo) > 0);
+}
+
+#[test]
+fn stripes_match_hash() {
+    let hash = framebuffer_hash(&stripes_image(0xCC, 0x77));
+
+    let framebuffer = run_frames(stripes_rom(), FRAMES, Renderer::Scanline);
+    let result = compare_screenshot(&framebuffer, &Reference::Hash(hash), None).unwrap();
+    assert_eq!(result, ScreenshotResult::Match);
+}
+
+fn check_rom_screenshot(name: &str, rom: &str, reference: &str, frames: u64, renderer: Renderer) {
+    let rom = std::fs::read(rom).unwrap_or_else(|_| panic!("Test ROM not found: {}", rom));
+    let diff = output_path(&format!("{}-diff.png", name));
+
+    let framebuffer = run_frames(rom, frames, renderer);
+    let result = compare_screenshot(&framebuffer, &Reference::Png(PathBuf::from(reference)), Some(&diff)).unwrap();
+    assert_eq!(result, ScreenshotResult::Match, "see {}", diff.display());
+}
+
+// The ROMs and reference images below are not distributed with the emulator,
+// see tests/test_roms.rs.
+macro_rules! screenshot_tests {
+    ($($name:ident: $rom:expr, $reference:expr, $frames:expr,)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[test]
+                #[ignore = "requires test ROMs in tests/roms"]
+                fn scanline() {
+                    check_rom_screenshot(concat!(stringify!($name), "-scanline"), concat!("tests/roms/", $rom), concat!("tests/roms/", $reference), $frames, Renderer::Scanline);
+                }
+
+                #[test]
+                #[ignore = "requires test ROMs in tests/roms"]
+                fn fifo() {
+                    check_rom_screenshot(concat!(stringify!($name), "-fifo"), concat!("tests/roms/", $rom), concat!("tests/roms/", $reference), $frames, Renderer::Fifo);
+                }
+            }
+        )*
+    }
+}
+
+screenshot_tests! {
+    dmg_acid2: "dmg-acid2/dmg-acid2.gb", "dmg-acid2/reference-dmg.png", 300,
+    mooneye_sprite_priority: "mooneye/manual-only/sprite_priority.gb", "mooneye/manual-only/sprite_priority-expected.png", 300,
+    cpu_instrs_01_special_screen: "cpu_instrs/individual/01-special.gb", "references/01-special.png", 3000,
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use std::net::{TcpListener, TcpStream};
+
+use common::{build_cgb_rom, build_rom};
+use crab_gb::cpu::bus::Bus;
+use crab_gb::cpu::link::TcpLink;
+use crab_gb::cpu::CPU;
+
+const SB: u16 = 0xFF01;
+const SC: u16 = 0xFF02;
+const IF: u16 = 0xFF0F;
+
+fn idle_cpu(rom: Vec<u8>) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.load_rom(rom);
+    cpu.set_serial_capture(true);
+    cpu
+}
+
+fn take_serial_interrupt(cpu: &mut CPU) -> bool {
+    let flag = cpu.bus_mut().read(IF);
+    cpu.bus_mut().write(IF, flag & !0x08);
+    (flag >> 3) & 1 == 1
+}
+
+fn is_transferring(cpu: &mut CPU) -> bool {
+    (cpu.bus_mut().read(SC) >> 7) & 1 == 1
+}
+
+fn start_transfer(cpu: &mut CPU, data: u8, control: u8) {
+    take_serial_interrupt(cpu);
+    cpu.bus_mut().write(SB, data);
+    cpu.bus_mut().write(SC, control);
+}
+
+#[test]
+fn internal_clock_transfer_takes_eight_bits() {
+    let mut cpu = idle_cpu(build_rom(&[0x18, 0xFE]));
+    start_transfer(&mut cpu, 0x42, 0x81);
+
+    // 8192 Hz, 128 M-cycles per bit
+    for _ in 0..1023 {
+        cpu.bus_mut().tick(1);
+    }
+    assert!(is_transferring(&mut cpu));
+    assert!(!take_serial_interrupt(&mut cpu));
+
+    cpu.bus_mut().tick(1);
+    assert!(!is_transferring(&mut cpu));
+    assert!(take_serial_interrupt(&mut cpu));
+    assert_eq!(cpu.bus_mut().read(SB), 0xFF);
+    assert_eq!(cpu.bus().serial_output(), [0x42]);
+
+    // Nothing is kept unless asked for
+    cpu.set_serial_capture(false);
+    start_transfer(&mut cpu, 0x43, 0x81);
+    assert!(cpu.bus().serial_output().is_empty());
+}
+
+#[test]
+fn cgb_fast_clock_transfer() {
+    let mut cpu = idle_cpu(build_cgb_rom(&[0x18, 0xFE]));
+    start_transfer(&mut cpu, 0x42, 0x83);
+    assert_eq!(cpu.bus_mut().read(SC), 0xFF);
+
+    // 262144 Hz, 4 M-cycles per bit
+    cpu.bus_mut().tick(31);
+    assert!(is_transferring(&mut cpu));
+    cpu.bus_mut().tick(1);
+    assert!(!is_transferring(&mut cpu));
+    assert!(take_serial_interrupt(&mut cpu));
+}
+
+#[test]
+fn external_clock_waits_without_link() {
+    let mut cpu = idle_cpu(build_rom(&[0x18, 0xFE]));
+    start_transfer(&mut cpu, 0x42, 0x80);
+    for _ in 0..10_000 {
+        cpu.bus_mut().tick(4);
+    }
+    assert!(is_transferring(&mut cpu));
+    assert!(!take_serial_interrupt(&mut cpu));
+    assert_eq!(cpu.bus_mut().read(SB), 0x42);
+}
+
+fn linked_pair() -> (TcpLink, TcpLink) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (TcpLink::new(server).unwrap(), TcpLink::new(client).unwrap())
+}
+
+#[test]
+fn link_cable_exchanges_bytes() {
+    let (first, second) = linked_pair();
+    let mut master = idle_cpu(build_rom(&[0x18, 0xFE]));
+    let mut slave = idle_cpu(build_rom(&[0x18, 0xFE]));
+    master.set_link(first);
+    slave.set_link(second);
+
+    start_transfer(&mut slave, 0x34, 0x80);
+    start_transfer(&mut master, 0x12, 0x81);
+
+    let mut cycles = 0;
+    while (is_transferring(&mut master) || is_transferring(&mut slave)) && cycles < 100_000_000 {
+        master.bus_mut().tick(4);
+        slave.bus_mut().tick(4);
+        cycles += 4;
+    }
+    // The master only finishes after shifting all eight bits
+    assert!(cycles >= 1024);
+
+    assert_eq!(master.bus_mut().read(SB), 0x34);
+    assert_eq!(slave.bus_mut().read(SB), 0x12);
+    assert!(take_serial_interrupt(&mut master));
+    assert!(take_serial_interrupt(&mut slave));
+}
+
+#[test]
+fn unplugged_link_cable_shifts_in_ones() {
+    let (first, second) = linked_pair();
+    let mut cpu = idle_cpu(build_rom(&[0x18, 0xFE]));
+    cpu.set_link(first);
+    drop(second);
+
+    start_transfer(&mut cpu, 0x12, 0x81);
+    let mut cycles = 0;
+    while is_transferring(&mut cpu) && cycles < 100_000_000 {
+        cpu.bus_mut().tick(4);
+        cycles += 4;
+    }
+    assert_eq!(cpu.bus_mut().read(SB), 0xFF);
+    assert!(take_serial_interrupt(&mut cpu));
+}
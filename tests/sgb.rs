@@ -0,0 +1,122 @@
+mod common;
+
+use common::{add_sgb_packet_routine, report_z, result_rom, set_sgb_flag, stripes_rom_with, FAIL, SEND_PACKET};
+use crab_gb::cpu::CPU;
+
+const FRAMES: u64 = 120;
+const SCREEN_WIDTH: usize = 160;
+
+fn run_sgb(rom: Vec<u8>) -> CPU {
+    let mut cpu = CPU::new();
+    cpu.set_sgb(true);
+    cpu.load_rom(rom);
+    cpu.set_serial_capture(true);
+    assert!(cpu.is_sgb());
+    while cpu.frame_count() < FRAMES {
+        cpu.step_instruction();
+    }
+    cpu
+}
+
+fn pixel(framebuffer: &[u8], x: usize, y: usize) -> [u8; 4] {
+    let index = (y * SCREEN_WIDTH + x) * 4;
+    framebuffer[index..index + 4].try_into().unwrap()
+}
+
+// Stripes of colour 1 and 2, coloured by PAL01 with palettes 0 and 1 given
+// to the cells by the `attributes` packet.
+fn sgb_palettes_rom(attributes: &[u8]) -> Vec<u8> {
+    let mut rom = stripes_rom_with(&[
+        0x21, 0x00, 0x04, // LD HL, 0x0400
+        0xCD, SEND_PACKET as u8, (SEND_PACKET >> 8) as u8, // CALL SEND_PACKET
+        0xCD, SEND_PACKET as u8, (SEND_PACKET >> 8) as u8, // CALL SEND_PACKET
+        0x18, 0xFE,       // JR -2
+    ]);
+    add_sgb_packet_routine(&mut rom);
+
+    // PAL01: red, then green, blue, black and white, red, black
+    rom[0x400..0x40F].copy_from_slice(&[
+        0x01, 0x1F, 0x00, 0xE0, 0x03, 0x00, 0x7C, 0x00, 0x00, 0xFF, 0x7F, 0x1F, 0x00, 0x00, 0x00,
+    ]);
+    rom[0x410..0x410 + attributes.len()].copy_from_slice(attributes);
+    set_sgb_flag(&mut rom);
+    rom
+}
+
+#[test]
+fn sgb_palettes_and_attributes() {
+    // ATTR_DIV: palette 0 left of column 10, palette 1 from it
+    let cpu = run_sgb(sgb_palettes_rom(&[0x31, 0x11, 0x0A]));
+    let framebuffer = cpu.get_framebuffer();
+
+    assert_eq!(pixel(&framebuffer, 0, 0), [0x00, 0xFF, 0x00, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 79, 1), [0x00, 0x00, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 80, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 159, 1), [0xFF, 0x00, 0x00, 0xFF]);
+
+    let border = cpu.get_sgb_border_frame().unwrap();
+    assert_eq!(border[0..4], [0xFF, 0x00, 0x00, 0xFF]);
+    assert_eq!(border[(40 * 256 + 48) * 4..(40 * 256 + 48) * 4 + 4], [0x00, 0xFF, 0x00, 0xFF]);
+}
+
+// ATTR_BLK colouring only outside a block also colours its border.
+#[test]
+fn sgb_attribute_block_outside() {
+    // One block from cell (2, 2) to (5, 5), palette 1 outside
+    let cpu = run_sgb(sgb_palettes_rom(&[0x21, 0x01, 0x04, 0x10, 0x02, 0x02, 0x05, 0x05]));
+    let framebuffer = cpu.get_framebuffer();
+
+    assert_eq!(pixel(&framebuffer, 0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 16, 16), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 40, 24), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 24, 24), [0x00, 0xFF, 0x00, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 39, 39), [0x00, 0x00, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 48, 48), [0xFF, 0xFF, 0xFF, 0xFF]);
+}
+
+// Inside and outside without the line leave the border as it was.
+#[test]
+fn sgb_attribute_block_inside_and_outside() {
+    // One block from cell (2, 2) to (5, 5), palette 1 inside and outside,
+    // palette 2 on the line
+    let cpu = run_sgb(sgb_palettes_rom(&[0x21, 0x01, 0x05, 0x19, 0x02, 0x02, 0x05, 0x05]));
+    let framebuffer = cpu.get_framebuffer();
+
+    assert_eq!(pixel(&framebuffer, 0, 0), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 24, 24), [0xFF, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 16, 16), [0x00, 0xFF, 0x00, 0xFF]);
+    assert_eq!(pixel(&framebuffer, 40, 25), [0x00, 0x00, 0xFF, 0xFF]);
+}
+
+// After MLT_REQ, reading P1 with no row selected returns the controller ID,
+// which moves to the next controller when P15 goes back high.
+fn sgb_multiplayer_rom() -> Vec<u8> {
+    let mut code = vec![
+        0x21, 0x00, 0x04, // LD HL, 0x0400
+        0xCD, SEND_PACKET as u8, (SEND_PACKET >> 8) as u8, // CALL SEND_PACKET
+        0xF0, 0x00,       // LDH A, (P1)
+        0xE6, 0x0F,       // AND 0x0F
+        0xFE, 0x0F,       // CP 0x0F
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0x3E, 0x10,       // LD A, 0x10
+        0xE0, 0x00,       // LDH (P1), A
+        0x3E, 0x30,       // LD A, 0x30
+        0xE0, 0x00,       // LDH (P1), A
+        0xF0, 0x00,       // LDH A, (P1)
+        0xE6, 0x0F,       // AND 0x0F
+        0xFE, 0x0E,       // CP 0x0E
+    ];
+    code.extend_from_slice(&report_z());
+    let mut rom = result_rom(&code);
+    add_sgb_packet_routine(&mut rom);
+    // MLT_REQ: two players
+    rom[0x400..0x402].copy_from_slice(&[0x89, 0x01]);
+    set_sgb_flag(&mut rom);
+    rom
+}
+
+#[test]
+fn sgb_multiplayer() {
+    let cpu = run_sgb(sgb_multiplayer_rom());
+    assert_eq!(cpu.bus().serial_output(), b"Passed");
+}
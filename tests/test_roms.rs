@@ -0,0 +1,446 @@
+mod common;
+
+use std::fs;
+
+use common::{build_rom, copy_code, report_z, result_rom, set_cgb_flag, wait_ly, FAIL, PRINT};
+use crab_gb::headless::{run_test_rom, TestReport, TestResult};
+
+const MAX_CYCLES: u64 = 200_000_000;
+
+// Prints `message` through the serial port.
+fn serial_rom(message: &str) -> Vec<u8> {
+    let mut rom = result_rom(&[
+        0x21, 0x00, 0x04, // LD HL, 0x0400
+        0xC3, PRINT as u8, (PRINT >> 8) as u8, // JP PRINT
+    ]);
+    rom[0x400..0x400 + message.len()].copy_from_slice(message.as_bytes());
+    rom
+}
+
+fn signature_rom(registers: [u8; 6]) -> Vec<u8> {
+    build_rom(&[
+        0x06, registers[0], // LD B, u8
+        0x0E, registers[1], // LD C, u8
+        0x16, registers[2], // LD D, u8
+        0x1E, registers[3], // LD E, u8
+        0x26, registers[4], // LD H, u8
+        0x2E, registers[5], // LD L, u8
+        0x40,               // LD B, B
+        0x18, 0xFE,         // JR -2
+    ])
+}
+
+// Checks that VRAM is blocked in mode 3 and OAM in mode 2, but VRAM is
+// accessible again in mode 0.
+fn access_blocking_rom() -> Vec<u8> {
+    let mut code = vec![
+        0xF0, 0x41, 0xE6, 0x03, 0xFE, 0x03, 0x20, 0xF8, // wait for mode 3
+        0x3E, 0x55,       // LD A, 0x55
+        0xEA, 0x00, 0x80, // LD (0x8000), A
+        0xFA, 0x00, 0x80, // LD A, (0x8000)
+        0xFE, 0xFF,       // CP 0xFF
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0xF0, 0x41, 0xE6, 0x03, 0xFE, 0x02, 0x20, 0xF8, // wait for mode 2
+        0x3E, 0x55,       // LD A, 0x55
+        0xEA, 0x00, 0xFE, // LD (0xFE00), A
+        0xFA, 0x00, 0xFE, // LD A, (0xFE00)
+        0xFE, 0xFF,       // CP 0xFF
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0xF0, 0x41, 0xE6, 0x03, 0xFE, 0x00, 0x20, 0xF8, // wait for mode 0
+        0x3E, 0x55,       // LD A, 0x55
+        0xEA, 0x00, 0x80, // LD (0x8000), A
+        0xFA, 0x00, 0x80, // LD A, (0x8000)
+        0xFE, 0x55,       // CP 0x55
+    ];
+    code.extend_from_slice(&report_z());
+    result_rom(&code)
+}
+
+// Enables only the LY=LYC STAT source with LYC = `lyc` and installs `handler`
+// at the STAT interrupt vector.
+fn lyc_interrupt_rom(lyc: u8, handler: &[u8], setup: &[u8], check: &[u8]) -> Vec<u8> {
+    let mut code = vec![
+        0x3E, lyc,  // LD A, lyc
+        0xE0, 0x45, // LDH (LYC), A
+        0x3E, 0x40, // LD A, 0x40
+        0xE0, 0x41, // LDH (STAT), A
+        0x3E, 0x02, // LD A, 0x02
+        0xE0, 0xFF, // LDH (IE), A
+    ];
+    code.extend_from_slice(&wait_ly(0x80));
+    code.extend_from_slice(&[
+        0xAF,       // XOR A
+        0xE0, 0x0F, // LDH (IF), A
+    ]);
+    code.extend_from_slice(setup);
+    code.push(0xFB); // EI
+    code.extend_from_slice(check);
+    code.extend_from_slice(&report_z());
+
+    let mut rom = result_rom(&code);
+    rom[0x48..0x48 + handler.len()].copy_from_slice(handler);
+    rom
+}
+
+// The LYC interrupt fires once per frame, not on every dot LY=LYC holds.
+fn lyc_once_per_frame_rom() -> Vec<u8> {
+    let mut check = Vec::new();
+    check.extend_from_slice(&wait_ly(0x00));
+    check.extend_from_slice(&wait_ly(0x80));
+    check.extend_from_slice(&[
+        0xF3,       // DI
+        0x78,       // LD A, B
+        0xFE, 0x01, // CP 1
+    ]);
+    lyc_interrupt_rom(0x10, &[0x04, 0xD9], &[0x06, 0x00], &check) // INC B; RETI / LD B, 0
+}
+
+// LY already reads 0 on line 153, so LYC=0 fires while still in VBlank.
+fn lyc_line_153_rom() -> Vec<u8> {
+    let handler = [
+        0xF0, 0x41, // LDH A, (STAT)
+        0x4F,       // LD C, A
+        0xD9,       // RETI
+    ];
+    let mut check = Vec::new();
+    check.extend_from_slice(&wait_ly(0x10));
+    check.extend_from_slice(&[
+        0xF3,       // DI
+        0x79,       // LD A, C
+        0xE6, 0x03, // AND 0x03
+        0xFE, 0x01, // CP 1
+    ]);
+    lyc_interrupt_rom(0x00, &handler, &[0x0E, 0x00], &check) // LD C, 0
+}
+
+// Writing STAT during VBlank raises the interrupt on DMG even with every
+// source disabled, but not in CGB mode.
+fn stat_write_bug_rom(cgb: bool) -> Vec<u8> {
+    let expected = if cgb { 0x00 } else { 0x02 };
+    let mut code = wait_ly(0x90).to_vec();
+    code.extend_from_slice(&[
+        0xAF,       // XOR A
+        0xE0, 0x0F, // LDH (IF), A
+        0xE0, 0x41, // LDH (STAT), A
+        0xF0, 0x0F, // LDH A, (IF)
+        0xE6, 0x02, // AND 0x02
+        0xFE, expected, // CP expected
+    ]);
+    code.extend_from_slice(&report_z());
+    let mut rom = result_rom(&code);
+    if cgb {
+        set_cgb_flag(&mut rom);
+    }
+    rom
+}
+
+// Enabling the LY=LYC source while LY already equals LYC is a rising edge of
+// the STAT line and raises the interrupt.
+fn stat_enable_lyc_rom(cgb: bool) -> Vec<u8> {
+    let mut code = vec![
+        0xAF,       // XOR A
+        0xE0, 0x41, // LDH (STAT), A
+        0x3E, 0x90, // LD A, 0x90
+        0xE0, 0x45, // LDH (LYC), A
+    ];
+    code.extend_from_slice(&wait_ly(0x90));
+    code.extend_from_slice(&[
+        0xAF,       // XOR A
+        0xE0, 0x0F, // LDH (IF), A
+        0x3E, 0x40, // LD A, 0x40
+        0xE0, 0x41, // LDH (STAT), A
+        0xF0, 0x0F, // LDH A, (IF)
+        0xE6, 0x02, // AND 0x02
+        0xFE, 0x02, // CP 0x02
+    ]);
+    code.extend_from_slice(&report_z());
+    let mut rom = result_rom(&code);
+    if cgb {
+        set_cgb_flag(&mut rom);
+    }
+    rom
+}
+
+// After turning the LCD back on, line 0 starts in mode 0 rather than mode 2.
+fn lcd_enable_rom() -> Vec<u8> {
+    let mut code = wait_ly(0x90).to_vec();
+    code.extend_from_slice(&[
+        0xAF,       // XOR A
+        0xE0, 0x40, // LDH (LCDC), A
+        0xF0, 0x41, // LDH A, (STAT)
+        0xE6, 0x03, // AND 0x03
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0x3E, 0x91, // LD A, 0x91
+        0xE0, 0x40, // LDH (LCDC), A
+        0xF0, 0x41, // LDH A, (STAT)
+        0xE6, 0x03, // AND 0x03
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0xF0, 0x44, // LDH A, (LY)
+        0xFE, 0x00, // CP 0
+    ]);
+    code.extend_from_slice(&report_z());
+    result_rom(&code)
+}
+
+// Runs OAM DMA from 0xE000 (decoded as 0xC000) with the routine in HRAM.
+// Work RAM reads during the transfer return the byte being copied.
+fn oam_dma_rom() -> Vec<u8> {
+    let mut code = vec![
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0xAF,             // XOR A
+        0x22,             // loop: LD (HL+), A
+        0x3C,             // INC A
+        0xFE, 0xA0,       // CP 0xA0
+        0x20, 0xFA,       // JR NZ, loop
+        0x3E, 0x5A,       // LD A, 0x5A
+        0xEA, 0x00, 0xC1, // LD (0xC100), A
+    ];
+    code.extend_from_slice(&copy_code(0x0200, 0xFF80, 12));
+    code.extend_from_slice(&[
+        0x3E, 0xE0,       // LD A, 0xE0
+        0xCD, 0x80, 0xFF, // CALL 0xFF80
+        0x78,             // LD A, B
+        0xFE, 0x5A,       // CP 0x5A
+        0xCA, FAIL as u8, (FAIL >> 8) as u8, // JP Z, FAIL
+    ]);
+    code.extend_from_slice(&wait_ly(0x90));
+    code.extend_from_slice(&[
+        0xFA, 0x9F, 0xFE, // LD A, (0xFE9F)
+        0xFE, 0x9F,       // CP 0x9F
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0xFA, 0x10, 0xFE, // LD A, (0xFE10)
+        0xFE, 0x10,       // CP 0x10
+    ]);
+    code.extend_from_slice(&report_z());
+
+    let mut rom = result_rom(&code);
+    rom[0x200..0x20C].copy_from_slice(&[
+        0xE0, 0x46,       // LDH (DMA), A
+        0xFA, 0x00, 0xC1, // LD A, (0xC100)
+        0x47,             // LD B, A
+        0x3E, 0x28,       // LD A, 40
+        0x3D,             // wait: DEC A
+        0x20, 0xFD,       // JR NZ, wait
+        0xC9,             // RET
+    ]);
+    rom
+}
+
+// CGB games start with A = 0x11 and can switch the WRAM bank at 0xD000.
+fn cgb_wram_banks_rom() -> Vec<u8> {
+    let mut code = vec![
+        0xFE, 0x11,       // CP 0x11
+        0xC2, FAIL as u8, (FAIL >> 8) as u8, // JP NZ, FAIL
+        0x3E, 0x11,       // LD A, 0x11
+        0xEA, 0x00, 0xD0, // LD (0xD000), A
+        0x3E, 0x02,       // LD A, 2
+        0xE0, 0x70,       // LDH (SVBK), A
+        0x3E, 0x22,       // LD A, 0x22
+        0xEA, 0x00, 0xD0, // LD (0xD000), A
+        0xAF,             // XOR A
+        0xE0, 0x70,       // LDH (SVBK), A (selects bank 1)
+        0xFA, 0x00, 0xD0, // LD A, (0xD000)
+        0xFE, 0x11,       // CP 0x11
+    ];
+    code.extend_from_slice(&report_z());
+    let mut rom = result_rom(&code);
+    set_cgb_flag(&mut rom);
+    rom
+}
+
+// General purpose DMA copies everything at once; HBlank DMA one block per
+// HBlank until cancelled.
+fn cgb_vram_dma_rom() -> Vec<u8> {
+    let jp_nz_fail = [0xC2, FAIL as u8, (FAIL >> 8) as u8];
+    let mut code = vec![
+        0x21, 0x00, 0xC0, // LD HL, 0xC000
+        0xAF,             // XOR A
+        0x22,             // fill: LD (HL+), A
+        0x3C,             // INC A
+        0xFE, 0x20,       // CP 0x20
+        0x20, 0xFA,       // JR NZ, fill
+    ];
+    code.extend_from_slice(&wait_ly(0x90));
+    code.extend_from_slice(&[
+        0x3E, 0xC0, 0xE0, 0x51, // source 0xC000
+        0xAF, 0xE0, 0x52,
+        0x3E, 0x80, 0xE0, 0x53, // destination 0x8000
+        0xAF, 0xE0, 0x54,
+        0x3E, 0x01, 0xE0, 0x55, // general purpose DMA, 2 blocks
+        0xF0, 0x55,             // LDH A, (HDMA5)
+        0xFE, 0xFF,             // CP 0xFF
+    ]);
+    code.extend_from_slice(&jp_nz_fail);
+    code.extend_from_slice(&[
+        0xFA, 0x1F, 0x80,       // LD A, (0x801F)
+        0xFE, 0x1F,             // CP 0x1F
+    ]);
+    code.extend_from_slice(&jp_nz_fail);
+    code.extend_from_slice(&[
+        0x3E, 0xC0, 0xE0, 0x51, // source 0xC000
+        0xAF, 0xE0, 0x52,
+        0x3E, 0x81, 0xE0, 0x53, // destination 0x8100
+        0xAF, 0xE0, 0x54,
+        0x3E, 0xFF, 0xE0, 0x55, // HBlank DMA, 128 blocks
+    ]);
+    code.extend_from_slice(&wait_ly(0x03));
+    code.extend_from_slice(&[
+        0xF0, 0x55,             // LDH A, (HDMA5)
+        0x47,                   // LD B, A
+        0xAF, 0xE0, 0x55,       // cancel
+        0xF0, 0x55,             // LDH A, (HDMA5)
+        0xFE, 0xFC,             // CP 0xFC
+    ]);
+    code.extend_from_slice(&jp_nz_fail);
+    code.extend_from_slice(&[
+        0x78,                   // LD A, B
+        0xFE, 0x7C,             // CP 0x7C
+    ]);
+    code.extend_from_slice(&jp_nz_fail);
+    code.extend_from_slice(&wait_ly(0x90));
+    code.extend_from_slice(&[
+        0xFA, 0x1F, 0x81,       // LD A, (0x811F)
+        0xFE, 0x1F,             // CP 0x1F
+    ]);
+    code.extend_from_slice(&report_z());
+
+    let mut rom = result_rom(&code);
+    set_cgb_flag(&mut rom);
+    rom
+}
+
+fn run_rom_file(path: &str) -> TestReport {
+    let rom = fs::read(path).unwrap_or_else(|_| panic!("Test ROM not found: {}", path));
+    run_test_rom(rom, MAX_CYCLES)
+}
+
+#[test]
+fn serial_passed() {
+    let report = run_test_rom(serial_rom("Test\nPassed\n"), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+    assert!(report.serial.starts_with("Test\nPassed"));
+}
+
+#[test]
+fn serial_failed() {
+    let report = run_test_rom(serial_rom("Test\nFailed #2\n"), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Failed);
+}
+
+#[test]
+fn fibonacci_signature_passed() {
+    let report = run_test_rom(signature_rom([3, 5, 8, 13, 21, 34]), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn fibonacci_signature_failed() {
+    let report = run_test_rom(signature_rom([0x42; 6]), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Failed);
+}
+
+#[test]
+fn vram_oam_access_blocking() {
+    let report = run_test_rom(access_blocking_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn stat_lyc_once_per_frame() {
+    let report = run_test_rom(lyc_once_per_frame_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn stat_lyc_line_153() {
+    let report = run_test_rom(lyc_line_153_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn stat_write_bug() {
+    let report = run_test_rom(stat_write_bug_rom(false), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn stat_write_enabling_lyc_source() {
+    let report = run_test_rom(stat_enable_lyc_rom(false), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+// Without the DMG glitch, CGB mode still sees the rising edge.
+#[test]
+fn stat_write_enabling_lyc_source_in_cgb_mode() {
+    let report = run_test_rom(stat_enable_lyc_rom(true), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn no_stat_write_bug_in_cgb_mode() {
+    let report = run_test_rom(stat_write_bug_rom(true), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn lcd_enable_first_line() {
+    let report = run_test_rom(lcd_enable_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn oam_dma() {
+    let report = run_test_rom(oam_dma_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn cgb_wram_banks() {
+    let report = run_test_rom(cgb_wram_banks_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn cgb_vram_dma() {
+    let report = run_test_rom(cgb_vram_dma_rom(), MAX_CYCLES);
+    assert_eq!(report.result, TestResult::Passed);
+}
+
+#[test]
+fn timeout() {
+    let report = run_test_rom(build_rom(&[0x18, 0xFE]), 1_000_000);
+    assert_eq!(report.result, TestResult::Timeout);
+    assert!(report.cycles >= 1_000_000);
+}
+
+// The ROMs below are not distributed with the emulator. Place them under
+// tests/roms (e.g. from https://github.com/retrio/gb-test-roms) and run
+// `cargo test -- --ignored`.
+macro_rules! rom_tests {
+    ($($name:ident: $path:expr,)*) => {
+        $(
+            #[test]
+            #[ignore = "requires test ROMs in tests/roms"]
+            fn $name() {
+                let report = run_rom_file(concat!("tests/roms/", $path));
+                assert_eq!(report.result, TestResult::Passed, "{}", report.serial);
+            }
+        )*
+    }
+}
+
+rom_tests! {
+    cpu_instrs_01_special: "cpu_instrs/individual/01-special.gb",
+    cpu_instrs_02_interrupts: "cpu_instrs/individual/02-interrupts.gb",
+    cpu_instrs_03_op_sp_hl: "cpu_instrs/individual/03-op sp,hl.gb",
+    cpu_instrs_04_op_r_imm: "cpu_instrs/individual/04-op r,imm.gb",
+    cpu_instrs_05_op_rp: "cpu_instrs/individual/05-op rp.gb",
+    cpu_instrs_06_ld_r_r: "cpu_instrs/individual/06-ld r,r.gb",
+    cpu_instrs_07_jr_jp_call_ret_rst: "cpu_instrs/individual/07-jr,jp,call,ret,rst.gb",
+    cpu_instrs_08_misc_instrs: "cpu_instrs/individual/08-misc instrs.gb",
+    cpu_instrs_09_op_r_r: "cpu_instrs/individual/09-op r,r.gb",
+    cpu_instrs_10_bit_ops: "cpu_instrs/individual/10-bit ops.gb",
+    cpu_instrs_11_op_a_hl: "cpu_instrs/individual/11-op a,(hl).gb",
+    mooneye_daa: "mooneye/acceptance/instr/daa.gb",
+    mooneye_boot_regs: "mooneye/acceptance/boot_regs-dmgABC.gb",
+}